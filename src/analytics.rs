@@ -0,0 +1,121 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A file and how many different versions of it the tracked revisions contain.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChurn {
+    pub file_name: String,
+    /// Distinct blobs, the first one included. A file that never changed has one version
+    pub versions: u32,
+    /// The revision that introduced the newest version
+    pub last_changed: String,
+}
+
+/// Size of a revision, and how much it adds on top of the revisions before it.
+#[derive(Debug, Clone, Serialize)]
+pub struct RevisionGrowth {
+    pub revision: String,
+    pub number: i64,
+    pub assets: u64,
+    /// Manifest size of every asset of the revision, shared ones included
+    pub total_size: u64,
+    /// Change of `total_size` from the previous revision, `None` for the first one
+    pub size_delta: Option<i64>,
+    /// Assets whose blob this revision introduced
+    pub unique_assets: u64,
+    /// Manifest size of those blobs that are mirrored, what storing the revision actually costs
+    pub unique_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GrowthReport {
+    /// Oldest first
+    pub revisions: Vec<RevisionGrowth>,
+    /// Mean of the size deltas, `None` with less than two revisions
+    pub average_size_delta: Option<f64>,
+    /// Mean of the unique bytes every revision added
+    pub average_unique_bytes: Option<f64>,
+}
+
+impl GrowthReport {
+    /// `revisions` have to be ordered oldest first, their deltas are filled in here.
+    pub fn new(mut revisions: Vec<RevisionGrowth>) -> Self {
+        let mut previous: Option<u64> = None;
+        for revision in &mut revisions {
+            revision.size_delta =
+                previous.map(|previous| revision.total_size as i64 - previous as i64);
+            previous = Some(revision.total_size);
+        }
+
+        let deltas: Vec<i64> = revisions.iter().filter_map(|r| r.size_delta).collect();
+        let average_size_delta = (!deltas.is_empty())
+            .then(|| deltas.iter().map(|&delta| delta as f64).sum::<f64>() / deltas.len() as f64);
+        let average_unique_bytes = (!revisions.is_empty()).then(|| {
+            revisions.iter().map(|r| r.unique_bytes as f64).sum::<f64>() / revisions.len() as f64
+        });
+
+        Self {
+            revisions,
+            average_size_delta,
+            average_unique_bytes,
+        }
+    }
+}
+
+/// A blob and the revisions sharing it through `origin_revision`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DedupChain {
+    pub file_name: String,
+    pub origin_revision: String,
+    /// Revisions containing the blob, the origin included
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DedupReport {
+    pub blobs: u64,
+    /// Asset rows across all revisions, every one of them points at one of the blobs
+    pub references: u64,
+    pub average_length: Option<f64>,
+    /// How many blobs have a chain of each length, shortest first
+    pub histogram: Vec<ChainLength>,
+    /// The longest chains, longest first
+    pub longest: Vec<DedupChain>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainLength {
+    pub length: u32,
+    pub blobs: u64,
+}
+
+impl DedupReport {
+    /// Keeps the `limit` longest chains.
+    pub fn new(mut chains: Vec<DedupChain>, limit: usize) -> Self {
+        let blobs = chains.len() as u64;
+        let references: u64 = chains.iter().map(|chain| u64::from(chain.length)).sum();
+
+        let mut histogram: BTreeMap<u32, u64> = BTreeMap::new();
+        for chain in &chains {
+            *histogram.entry(chain.length).or_default() += 1;
+        }
+
+        chains.sort_by(|a, b| {
+            b.length
+                .cmp(&a.length)
+                .then_with(|| a.file_name.cmp(&b.file_name))
+        });
+        chains.truncate(limit);
+
+        Self {
+            blobs,
+            references,
+            average_length: (blobs > 0).then(|| references as f64 / blobs as f64),
+            histogram: histogram
+                .into_iter()
+                .map(|(length, blobs)| ChainLength { length, blobs })
+                .collect(),
+            longest: chains,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Archives Wizard101 revisions and serves them over HTTP.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Runs the revision checker and file server if omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Shows what the revision checker would download for the current revision, without writing anything
+    Plan {
+        /// Also list every asset that would be downloaded
+        #[arg(long)]
+        list: bool,
+    },
+    /// Removes revisions the [retention] policy doesn't keep, and the files only they used
+    Gc {
+        /// Only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Compares the database against storage and reports missing, orphaned and damaged files
+    Reconcile {
+        /// Delete stray files and download missing or damaged ones again
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Reports which files change most often, how much every revision adds and how far blobs are shared
+    Analytics {
+        /// How many files and chains to list
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Registers the revisions of a v3.x save directory and adopts their files
+    ImportV3 {
+        /// The v3.x save directory, containing one directory per revision
+        directory: PathBuf,
+    },
+    /// Registers a revision from a local directory or tarball instead of the patch server
+    Ingest {
+        /// Name of the revision, e.g. V_r773351.Wizard_1_570_0_Live
+        revision: String,
+        /// Directory or .tar/.tar.gz/.tar.zst archive with the revision's files
+        source: PathBuf,
+        /// Manifest to validate against, `LatestFileList.xml` inside the source if omitted
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+    /// Writes revisions, their database rows and the files they introduced into a .tar.zst bundle
+    Export {
+        /// Revisions to export
+        #[arg(required = true)]
+        revisions: Vec<String>,
+        /// Path of the bundle to write
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Imports a bundle written by `export`
+    Import {
+        /// Path of the bundle
+        bundle: PathBuf,
+    },
+}
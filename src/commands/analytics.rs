@@ -0,0 +1,65 @@
+use crate::{config::AppConfig, db::Database};
+use indicatif::HumanBytes;
+
+/// Prints which files change most often, how revisions grow and how far blobs are shared.
+pub async fn analytics(config: &AppConfig, limit: usize) -> miette::Result<()> {
+    let db = Database::open_read_only(&config.database.path).await?;
+    let churn = db.most_changed_files(limit).await?;
+    let growth = db.revision_growth().await?;
+    let dedup = db.dedup_chains(limit).await?;
+
+    println!("Most changed files:");
+    for file in &churn {
+        println!(
+            "{:>6} versions  {} (last changed in {})",
+            file.versions, file.file_name, file.last_changed
+        );
+    }
+
+    println!();
+    println!("Growth per revision:");
+    for revision in &growth.revisions {
+        println!(
+            "  {}: {} assets, {} total ({}), {} new assets, {} unique",
+            revision.revision,
+            revision.assets,
+            HumanBytes(revision.total_size),
+            revision
+                .size_delta
+                .map_or_else(|| "first".to_string(), signed_bytes),
+            revision.unique_assets,
+            HumanBytes(revision.unique_bytes)
+        );
+    }
+    if let Some(delta) = growth.average_size_delta {
+        println!("Average growth:       {}", signed_bytes(delta as i64));
+    }
+    if let Some(unique) = growth.average_unique_bytes {
+        println!("Average unique bytes: {}", HumanBytes(unique as u64));
+    }
+
+    println!();
+    println!(
+        "Dedup chains: {} blobs referenced by {} asset rows, {:.2} revisions per blob on average",
+        dedup.blobs,
+        dedup.references,
+        dedup.average_length.unwrap_or_default()
+    );
+    for bucket in &dedup.histogram {
+        println!("{:>6} revisions  {} blobs", bucket.length, bucket.blobs);
+    }
+    println!("Longest chains:");
+    for chain in &dedup.longest {
+        println!(
+            "{:>6} revisions  {} (stored by {})",
+            chain.length, chain.file_name, chain.origin_revision
+        );
+    }
+
+    Ok(())
+}
+
+fn signed_bytes(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    format!("{sign}{}", HumanBytes(bytes.unsigned_abs()))
+}
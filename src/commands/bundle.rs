@@ -0,0 +1,361 @@
+use crate::{
+    commands::ingest::extract,
+    config::AppConfig,
+    db::{AssetRecord, Database, RevisionMetadata},
+    errors::BundleError,
+    storage::{Storage, StorageBackend, StorageReader, asset_key, zstd_key},
+    utils::file_crc,
+    wizard_patcher::WizardPatcher,
+};
+use async_compression::tokio::bufread::ZstdDecoder;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Component, Path},
+};
+use tokio::{io::BufReader, runtime::Handle};
+use tokio_util::io::SyncIoBridge;
+use tracing::debug;
+
+const MANIFESTS: [&str; 2] = ["LatestFileList.bin", "LatestFileList.xml"];
+const BUNDLE_VERSION: u32 = 1;
+
+/// `bundle.json`, the first entry of every bundle.
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleIndex {
+    version: u32,
+    /// Oldest first, so origin revisions come before the revisions pointing at them
+    revisions: Vec<RevisionMetadata>,
+}
+
+/// A file that goes into the bundle as `{revision}/{file}`.
+struct BundleEntry {
+    path: String,
+    key: String,
+    size: u64,
+    /// Stored zstd-compressed, bundles always contain the plain file
+    compressed: bool,
+}
+
+/// Writes `revisions` into a single `.tar.zst` bundle: `bundle.json` with their database rows, their manifests,
+/// and the blobs they introduced. Blobs they share with older revisions are only referenced.
+pub async fn export(config: &AppConfig, revisions: &[String], output: &Path) -> miette::Result<()> {
+    let db = Database::init(&config.database.path).await?;
+    let storage = Storage::from_config(config)?;
+
+    let mut index = BundleIndex {
+        version: BUNDLE_VERSION,
+        revisions: Vec::new(),
+    };
+    for revision in revisions {
+        let metadata = db
+            .get_revision_metadata(revision.clone())
+            .await?
+            .ok_or_else(|| BundleError::UnknownRevision(revision.clone()))?;
+        index.revisions.push(metadata);
+    }
+    index.revisions.sort_by_key(|metadata| metadata.number);
+
+    let mut entries = Vec::new();
+    for metadata in &index.revisions {
+        for manifest in MANIFESTS {
+            let key = asset_key(&metadata.name, manifest);
+            if let Some(size) = storage.head(&key).await? {
+                entries.push(BundleEntry {
+                    path: key.clone(),
+                    key,
+                    size,
+                    compressed: false,
+                });
+            }
+        }
+
+        let blobs = metadata
+            .assets
+            .iter()
+            .filter(|asset| asset.mirrored && asset.origin_revision == metadata.name);
+        for asset in blobs {
+            let key = asset_key(&metadata.name, &asset.file_name);
+            let size = u64::from(asset.size);
+
+            // The tar header needs the size up front, so only an uncompressed copy of the right size is taken as-is
+            let entry = if storage.head(&key).await? == Some(size) {
+                BundleEntry {
+                    path: key.clone(),
+                    key,
+                    size,
+                    compressed: false,
+                }
+            } else if storage.exists(&zstd_key(&key)).await? {
+                BundleEntry {
+                    path: key.clone(),
+                    key: zstd_key(&key),
+                    size,
+                    compressed: true,
+                }
+            } else {
+                return Err(BundleError::MissingBlob(key).into());
+            };
+            entries.push(entry);
+        }
+    }
+
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let index_json = serde_json::to_vec_pretty(&index).expect("bundle index is serializable");
+    let level = config
+        .fetcher
+        .compression
+        .as_ref()
+        .map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |compression| {
+            compression.level
+        });
+    let output = output.to_path_buf();
+    let output_path = output.clone();
+    let handle = Handle::current();
+
+    tokio::task::spawn_blocking(move || -> miette::Result<()> {
+        let write_error = |e| BundleError::Write(e, output.clone());
+
+        let file = File::create(&output).map_err(write_error)?;
+        let encoder = zstd::Encoder::new(file, level).map_err(write_error)?;
+        let mut builder = tar::Builder::new(encoder);
+
+        append(
+            &mut builder,
+            "bundle.json",
+            index_json.len() as u64,
+            &index_json[..],
+        )
+        .map_err(write_error)?;
+
+        for entry in entries {
+            let reader = handle.block_on(storage.get_range(&entry.key, None))?;
+            let reader: StorageReader = if entry.compressed {
+                Box::pin(ZstdDecoder::new(BufReader::new(reader)))
+            } else {
+                reader
+            };
+
+            debug!(path = %entry.path, "adding file to bundle");
+            append(
+                &mut builder,
+                &entry.path,
+                entry.size,
+                SyncIoBridge::new_with_handle(reader, handle.clone()),
+            )
+            .map_err(write_error)?;
+        }
+
+        builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .map_err(write_error)?;
+
+        Ok(())
+    })
+    .await
+    .map_err(|e| BundleError::Write(e.into(), output_path))??;
+
+    println!(
+        "Exported {} revisions with {} of files",
+        index.revisions.len(),
+        HumanBytes(total)
+    );
+
+    Ok(())
+}
+
+fn append<W, R>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    size: u64,
+    reader: R,
+) -> std::io::Result<()>
+where
+    W: std::io::Write,
+    R: std::io::Read,
+{
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    builder.append_data(&mut header, path, reader)
+}
+
+/// Imports a bundle written by `export`.
+///
+/// Revisions that are already tracked are skipped. Everything else is checked before anything is written: names and
+/// paths have to be sane, every blob has to be in the bundle with the size and CRC its row lists, and every
+/// `origin_revision` has to be tracked already or come with the bundle. Blobs of tracked revisions are looked up again
+/// by name, CRC and size, since the exporting instance may store them with a different revision.
+pub async fn import(config: &AppConfig, bundle: &Path) -> miette::Result<()> {
+    let staging = extract(bundle.to_path_buf()).await?;
+    let directory = staging.path();
+
+    let index_path = directory.join("bundle.json");
+    let index = std::fs::read(&index_path)
+        .map_err(|_| BundleError::Invalid("bundle.json is missing".to_string()))?;
+    let mut index: BundleIndex = serde_json::from_slice(&index)
+        .map_err(|e| BundleError::Invalid(format!("bundle.json can't be parsed: {e}")))?;
+    if index.version != BUNDLE_VERSION {
+        return Err(BundleError::Invalid(format!("unsupported version {}", index.version)).into());
+    }
+    index.revisions.sort_by_key(|metadata| metadata.number);
+
+    let db = Database::init(&config.database.path).await?;
+    let storage = Storage::from_config(config)?;
+
+    let mut known: HashSet<String> = db.list_revisions().await?.into_iter().collect();
+    let (skipped, mut revisions): (Vec<_>, Vec<_>) = index
+        .revisions
+        .into_iter()
+        .partition(|metadata| known.contains(&metadata.name));
+
+    let mut problems = Vec::new();
+    for metadata in &mut revisions {
+        problems.extend(resolve_origins(&db, metadata, &known).await?);
+    }
+    for metadata in &revisions {
+        problems.extend(validate(directory, metadata, &known));
+        known.insert(metadata.name.clone());
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!("  - {problem}");
+        }
+        return Err(BundleError::Validation(problems.len()).into());
+    }
+
+    let compression = config.fetcher.compression.as_ref();
+    let mut moved_bytes = 0;
+    for metadata in &revisions {
+        let name = metadata.name.clone();
+        let blobs: Vec<String> = metadata
+            .assets
+            .iter()
+            .filter(|asset| asset.mirrored && asset.origin_revision == name)
+            .map(|asset| asset.file_name.clone())
+            .collect();
+
+        db.insert_replicated_revision(metadata.clone()).await?;
+
+        for manifest in MANIFESTS {
+            let path = directory.join(&name).join(manifest);
+            if path.is_file() {
+                storage
+                    .move_file(&asset_key(&name, manifest), &path, None)
+                    .await?;
+            }
+        }
+        for file_name in &blobs {
+            let key = asset_key(&name, file_name);
+            if storage.exists(&key).await? || storage.exists(&zstd_key(&key)).await? {
+                continue;
+            }
+
+            let path = directory.join(&name).join(file_name);
+            moved_bytes += storage.move_file(&key, &path, compression).await?;
+        }
+
+        println!("Imported {name} ({} blobs)", blobs.len());
+    }
+    for metadata in &skipped {
+        println!("Skipped {}, it's already tracked", metadata.name);
+    }
+    println!("Stored {}", HumanBytes(moved_bytes));
+
+    Ok(())
+}
+
+/// Points the rows whose blob is stored with an already tracked revision at the revision storing it here.
+/// Returns the rows no tracked revision has a matching blob for.
+async fn resolve_origins(
+    db: &Database,
+    metadata: &mut RevisionMetadata,
+    tracked: &HashSet<String>,
+) -> miette::Result<Vec<String>> {
+    let shared: Vec<&mut AssetRecord> = metadata
+        .assets
+        .iter_mut()
+        .filter(|asset| tracked.contains(&asset.origin_revision))
+        .collect();
+    let blobs = shared
+        .iter()
+        .map(|asset| (asset.file_name.clone(), asset.crc, asset.size))
+        .collect();
+    let origins = db.find_origins(blobs).await?;
+
+    let mut problems = Vec::new();
+    for (asset, origin) in shared.into_iter().zip(origins) {
+        match origin {
+            Some(origin) => asset.origin_revision = origin,
+            None => problems.push(format!(
+                "{}/{}: stored with {}, but no tracked revision has a blob with its name, CRC and size",
+                metadata.name, asset.file_name, asset.origin_revision
+            )),
+        }
+    }
+
+    Ok(problems)
+}
+
+fn validate(directory: &Path, metadata: &RevisionMetadata, known: &HashSet<String>) -> Vec<String> {
+    let name = &metadata.name;
+    if WizardPatcher::extract_revision_number(name).ok() != Some(metadata.number) {
+        return vec![format!("{name}: not a valid revision name or number")];
+    }
+
+    let mut problems = Vec::new();
+    for asset in &metadata.assets {
+        let file = &asset.file_name;
+        if !is_relative(file) {
+            problems.push(format!("{name}/{file}: invalid file name"));
+            continue;
+        }
+
+        if asset.origin_revision != *name {
+            if !known.contains(&asset.origin_revision) {
+                problems.push(format!(
+                    "{name}/{file}: stored with {}, which is neither tracked nor an older revision in the bundle",
+                    asset.origin_revision
+                ));
+            }
+            continue;
+        }
+
+        if asset.mirrored {
+            let path = directory.join(name).join(file);
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.is_file() && meta.len() == u64::from(asset.size) => {
+                    match file_crc(&path) {
+                        Ok(crc) if crc == asset.crc => {}
+                        Ok(crc) => problems.push(format!(
+                            "{name}/{file}: CRC {crc}, but its row lists {}",
+                            asset.crc
+                        )),
+                        Err(e) => problems.push(format!("{name}/{file}: can't be read ({e})")),
+                    }
+                }
+                Ok(meta) => problems.push(format!(
+                    "{name}/{file}: {} bytes, but its row lists {}",
+                    meta.len(),
+                    asset.size
+                )),
+                Err(_) => problems.push(format!("{name}/{file}: missing")),
+            }
+        }
+    }
+
+    problems
+}
+
+/// Whether `file_name` stays inside its revision directory.
+fn is_relative(file_name: &str) -> bool {
+    !file_name.is_empty()
+        && Path::new(file_name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
@@ -0,0 +1,44 @@
+use crate::{
+    config::AppConfig, db::Database, errors::ConfigError, retention::collect_garbage,
+    storage::Storage,
+};
+use indicatif::HumanBytes;
+
+/// Applies the `[retention]` policy once and prints what was (or, with `dry_run`, would be) removed.
+pub async fn gc(config: &AppConfig, dry_run: bool) -> miette::Result<()> {
+    let retention = config
+        .retention
+        .as_ref()
+        .ok_or(ConfigError::MissingSection("retention"))?;
+
+    let db = Database::init(&config.database.path).await?;
+    let storage = Storage::from_config(config)?;
+    let report = collect_garbage(&db, &storage, retention, dry_run).await?;
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    println!(
+        "{verb} {} of {} revisions",
+        report.removed.len(),
+        report.removed.len() + report.kept.len()
+    );
+    for revision in &report.removed {
+        println!("  - {revision}");
+    }
+    println!(
+        "{} blobs still in use {} moved to a retained revision",
+        report.moved,
+        if dry_run { "would be" } else { "were" }
+    );
+    println!(
+        "{} objects {}, {} freed",
+        report.deleted_objects,
+        if dry_run {
+            "would be deleted"
+        } else {
+            "deleted"
+        },
+        HumanBytes(report.freed_bytes)
+    );
+
+    Ok(())
+}
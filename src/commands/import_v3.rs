@@ -0,0 +1,145 @@
+use crate::{
+    config::{AppConfig, CompressionConfig},
+    db::Database,
+    errors::ImportError,
+    revision::Revision,
+    storage::{Storage, StorageBackend, asset_key, zstd_key},
+    wizard_patcher::WizardPatcher,
+    xml_parser::parse_file_list,
+};
+use indicatif::HumanBytes;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+const MANIFESTS: [&str; 2] = ["LatestFileList.bin", "LatestFileList.xml"];
+
+/// Registers every revision of a v3.x save directory, oldest first, and adopts its files into storage.
+///
+/// Revisions go through `insert_new_revision`, so deduplication matches what the revision checker would have done.
+/// Only the files a revision introduced are copied, files that are already in storage (e.g. because the v3.x
+/// directory is the `save_directory`) are left alone.
+pub async fn import_v3(config: &AppConfig, directory: &Path) -> miette::Result<()> {
+    let revisions = find_revisions(directory)?;
+    if revisions.is_empty() {
+        return Err(ImportError::NoRevisions(directory.to_path_buf()).into());
+    }
+
+    let db = Database::init(&config.database.path).await?;
+    let storage = Storage::from_config(config)?;
+
+    let mut copied_bytes = 0;
+    let mut total_missing = 0;
+    for (revision, path) in revisions {
+        let assets = parse_file_list(path.join("LatestFileList.xml"))?;
+        let asset_count = assets.len();
+
+        let queued = db
+            .insert_new_revision(revision.clone(), None, assets)
+            .await?;
+        for manifest in MANIFESTS {
+            adopt(&storage, &revision, &path, manifest, None, None).await?;
+        }
+
+        let (mut copied, mut present, mut missing) = (0, 0, 0);
+        for asset in &queued {
+            match adopt(
+                &storage,
+                &revision,
+                &path,
+                &asset.file_name,
+                Some(asset.size),
+                config.fetcher.compression.as_ref(),
+            )
+            .await?
+            {
+                Adoption::Present => present += 1,
+                Adoption::Copied(bytes) => {
+                    copied += 1;
+                    copied_bytes += bytes;
+                }
+                Adoption::Missing => missing += 1,
+            }
+        }
+        total_missing += missing;
+
+        println!(
+            "{revision}: {asset_count} assets, {} deduplicated, {copied} copied, {present} already stored, {missing} missing",
+            asset_count - queued.len()
+        );
+    }
+
+    println!("Copied {} into storage", HumanBytes(copied_bytes));
+    if total_missing > 0 {
+        warn!(
+            "{total_missing} files weren't in the v3.x directory, `aurorium reconcile` lists them"
+        );
+    }
+
+    Ok(())
+}
+
+/// Revision directories below `directory`, oldest first, so later revisions deduplicate against earlier ones.
+fn find_revisions(directory: &Path) -> miette::Result<Vec<(Revision, PathBuf)>> {
+    let entries =
+        std::fs::read_dir(directory).map_err(|e| ImportError::Read(e, directory.to_path_buf()))?;
+
+    let mut revisions = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| ImportError::Read(e, directory.to_path_buf()))?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        let Ok(number) = WizardPatcher::extract_revision_number(&name) else {
+            continue;
+        };
+        if !path.join("LatestFileList.xml").is_file() {
+            warn!("Skipping {name}, it has no LatestFileList.xml");
+            continue;
+        }
+
+        revisions.push((Revision { name, number }, path));
+    }
+    revisions.sort_by_key(|(revision, _)| revision.number);
+
+    Ok(revisions)
+}
+
+enum Adoption {
+    Present,
+    /// Bytes copied
+    Copied(u64),
+    Missing,
+}
+
+/// Copies `file_name` of a revision into storage unless it's already there. Manifests are never compressed.
+/// With `size`, files of a different size are treated as missing, since they can't be what the manifest describes.
+async fn adopt(
+    storage: &Storage,
+    revision: &Revision,
+    path: &Path,
+    file_name: &str,
+    size: Option<u32>,
+    compression: Option<&CompressionConfig>,
+) -> miette::Result<Adoption> {
+    let key = asset_key(&revision.name, file_name);
+    let matches = |stored: u64| size.is_none_or(|size| stored == u64::from(size));
+
+    if storage.head(&key).await?.is_some_and(matches) || storage.exists(&zstd_key(&key)).await? {
+        return Ok(Adoption::Present);
+    }
+
+    let source = path.join(file_name);
+    let source_size = match std::fs::metadata(&source) {
+        Ok(meta) if meta.is_file() && matches(meta.len()) => meta.len(),
+        Ok(meta) => {
+            debug!(path = %source.display(), size = meta.len(), "not adopting file");
+            return Ok(Adoption::Missing);
+        }
+        Err(_) => return Ok(Adoption::Missing),
+    };
+
+    storage.put_file(&key, &source, compression).await?;
+    debug!(key = %key, "copied file");
+
+    Ok(Adoption::Copied(source_size))
+}
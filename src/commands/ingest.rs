@@ -0,0 +1,190 @@
+use crate::{
+    config::AppConfig,
+    db::Database,
+    errors::ImportError,
+    revision::{Asset, Revision},
+    storage::{Storage, StorageBackend, asset_key, zstd_key},
+    utils::file_crc,
+    wizard_patcher::WizardPatcher,
+    xml_parser::parse_file_list,
+};
+use flate2::read::GzDecoder;
+use indicatif::HumanBytes;
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+use tracing::debug;
+
+/// Registers a revision that was obtained some other way than from the patch server, e.g. from another archivist.
+///
+/// `source` is a directory or tarball holding the revision's files (and usually its manifest). Everything is validated
+/// against the manifest before anything is written, then the revision goes through `insert_new_revision` and the files
+/// it introduces are moved into storage. Files that deduplicate against earlier revisions and the manifests are left
+/// where they are, the manifests are copied.
+pub async fn ingest(
+    config: &AppConfig,
+    revision_name: &str,
+    source: &Path,
+    manifest: Option<&Path>,
+) -> miette::Result<()> {
+    let revision = Revision {
+        name: revision_name.to_string(),
+        number: WizardPatcher::extract_revision_number(revision_name)?,
+    };
+
+    // Keeps extracted tarballs around until everything was moved
+    let (_staging, directory) = stage(source, revision_name).await?;
+    let manifest = manifest.map_or_else(|| directory.join("LatestFileList.xml"), Path::to_path_buf);
+    let assets = parse_file_list(&manifest)?;
+    let asset_count = assets.len();
+
+    let db = Database::init(&config.database.path).await?;
+    let storage = Storage::from_config(config)?;
+
+    let plan = db
+        .plan_new_revision(revision.clone(), assets.clone())
+        .await?;
+    let mut pending = Vec::new();
+    let mut problems = Vec::new();
+    for asset in &plan.queued {
+        let key = asset_key(&revision.name, &asset.file_name);
+        if storage.exists(&key).await? || storage.exists(&zstd_key(&key)).await? {
+            continue;
+        }
+
+        match check(&directory, asset) {
+            Ok(path) => pending.push((key, path)),
+            Err(problem) => problems.push(format!("{}: {problem}", asset.file_name)),
+        }
+    }
+    // Files that deduplicate aren't needed, but if they were supplied they still have to be right
+    for (asset, _) in &plan.deduplicated {
+        if directory.join(&asset.file_name).exists()
+            && let Err(problem) = check(&directory, asset)
+        {
+            problems.push(format!("{}: {problem}", asset.file_name));
+        }
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!("  - {problem}");
+        }
+        return Err(ImportError::Validation {
+            revision: revision.name,
+            problems: problems.len(),
+        }
+        .into());
+    }
+
+    db.insert_new_revision(revision.clone(), None, assets)
+        .await?;
+
+    let compression = config.fetcher.compression.as_ref();
+    let mut moved_bytes = 0;
+    for (key, path) in &pending {
+        moved_bytes += storage.move_file(key, path, compression).await?;
+        debug!(key = %key, "moved file into storage");
+    }
+
+    let manifests = [
+        (manifest, "LatestFileList.xml"),
+        (directory.join("LatestFileList.bin"), "LatestFileList.bin"),
+    ];
+    for (path, name) in manifests {
+        let key = asset_key(&revision.name, name);
+        if path.is_file() && !storage.exists(&key).await? {
+            storage.put_file(&key, &path, None).await?;
+        }
+    }
+
+    println!(
+        "{revision}: {asset_count} assets, {} deduplicated, {} moved into storage ({})",
+        plan.deduplicated.len(),
+        pending.len(),
+        HumanBytes(moved_bytes)
+    );
+
+    Ok(())
+}
+
+/// Returns the directory the revision's files are in, extracting `source` first if it's a tarball.
+///
+/// Both may wrap everything in a directory named after the revision, which is looked through.
+async fn stage(source: &Path, revision_name: &str) -> miette::Result<(Option<TempDir>, PathBuf)> {
+    let (staging, directory) = if source.is_dir() {
+        (None, source.to_path_buf())
+    } else {
+        let staging = extract(source.to_path_buf()).await?;
+        let directory = staging.path().to_path_buf();
+        (Some(staging), directory)
+    };
+
+    let nested = directory.join(revision_name);
+    if nested.is_dir() {
+        return Ok((staging, nested));
+    }
+
+    Ok((staging, directory))
+}
+
+/// Extracts a tarball into a temporary directory, picking the decompression by the file extension.
+pub async fn extract(archive: PathBuf) -> miette::Result<TempDir> {
+    let name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let staging = tempfile::tempdir().map_err(|e| ImportError::Extract(e, archive.clone()))?;
+    let target = staging.path().to_path_buf();
+    let archive_path = archive.clone();
+
+    tokio::task::spawn_blocking(move || -> Result<(), ImportError> {
+        let file = File::open(&archive).map_err(|e| ImportError::Read(e, archive.clone()))?;
+        let file = BufReader::new(file);
+
+        let reader: Box<dyn Read> = if name.ends_with(".tar") {
+            Box::new(file)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Box::new(GzDecoder::new(file))
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Box::new(
+                zstd::Decoder::with_buffer(file)
+                    .map_err(|e| ImportError::Extract(e, archive.clone()))?,
+            )
+        } else {
+            return Err(ImportError::UnsupportedArchive(archive));
+        };
+
+        // `unpack` refuses entries that would end up outside of `target`
+        tar::Archive::new(reader)
+            .unpack(&target)
+            .map_err(|e| ImportError::Extract(e, archive))
+    })
+    .await
+    .map_err(|e| ImportError::Extract(e.into(), archive_path))??;
+
+    Ok(staging)
+}
+
+/// Checks that the supplied copy of `asset` exists and has the size and CRC the manifest lists.
+fn check(directory: &Path, asset: &Asset) -> Result<PathBuf, String> {
+    let path = directory.join(&asset.file_name);
+    match std::fs::metadata(&path) {
+        Ok(meta) if !meta.is_file() => Err("not a file".to_string()),
+        Ok(meta) if meta.len() != u64::from(asset.size) => Err(format!(
+            "{} bytes, but the manifest lists {}",
+            meta.len(),
+            asset.size
+        )),
+        Ok(_) => match file_crc(&path) {
+            Ok(crc) if crc == asset.crc => Ok(path),
+            Ok(crc) => Err(format!("CRC {crc}, but the manifest lists {}", asset.crc)),
+            Err(e) => Err(format!("can't be read ({e})")),
+        },
+        Err(_) => Err("missing".to_string()),
+    }
+}
@@ -0,0 +1,7 @@
+pub mod analytics;
+pub mod bundle;
+pub mod gc;
+pub mod import_v3;
+pub mod ingest;
+pub mod plan;
+pub mod reconcile;
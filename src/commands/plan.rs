@@ -0,0 +1,122 @@
+use crate::{
+    config::{AppConfig, PatchConfig},
+    db::{Database, RevisionPlan},
+    fetcher::{filter::MirrorFilter, manifest_fetcher::ManifestFetcher},
+    revision::Asset,
+    storage::{Storage, StorageBackend, asset_key, zstd_key},
+    wizard_patcher::WizardPatcher,
+};
+use indicatif::HumanBytes;
+use std::{collections::HashSet, path::Path};
+
+/// Performs the handshake and parses the manifest like the revision checker, then reports what
+/// `insert_new_revision` would queue. Nothing is written to the database or storage.
+pub async fn plan(config: &AppConfig, list: bool) -> miette::Result<()> {
+    let PatchConfig { host, port } = &config.patch;
+    let wizard_patcher = WizardPatcher::check_revision(host, port).await?;
+    let storage = Storage::from_config(config)?;
+
+    let manifest_fetcher = ManifestFetcher::new(wizard_patcher.clone(), storage.clone())?;
+    let assets = manifest_fetcher.peek_xml_manifest().await?;
+    let manifest_count = assets.len();
+    let manifest_size = total_size(&assets);
+
+    // Without a database every asset is new, and creating one would be a write
+    let plan = if Path::new(&config.database.path).exists() {
+        Database::open_read_only(&config.database.path)
+            .await?
+            .plan_new_revision(wizard_patcher.revision.clone(), assets)
+            .await?
+    } else {
+        RevisionPlan {
+            queued: assets,
+            ..Default::default()
+        }
+    };
+
+    let mirror_filter = config
+        .fetcher
+        .filter
+        .as_ref()
+        .map(MirrorFilter::new)
+        .transpose()?;
+    let (queued, skipped) = match &mirror_filter {
+        Some(mirror_filter) => mirror_filter.split(plan.queued),
+        None => (plan.queued, vec![]),
+    };
+
+    // The asset fetcher skips whatever an earlier, interrupted check already stored
+    let mut downloads = Vec::with_capacity(queued.len());
+    let mut stored = Vec::new();
+    for asset in queued {
+        let key = asset_key(&wizard_patcher.revision.name, &asset.file_name);
+        if storage.exists(&key).await? || storage.exists(&zstd_key(&key)).await? {
+            stored.push(asset);
+        } else {
+            downloads.push(asset);
+        }
+    }
+
+    let deduplicated: Vec<&Asset> = plan.deduplicated.iter().map(|(asset, _)| asset).collect();
+    let origins: HashSet<&str> = plan
+        .deduplicated
+        .iter()
+        .map(|(_, origin)| origin.as_str())
+        .collect();
+
+    println!("Revision:     {}", wizard_patcher.revision);
+    println!(
+        "Status:       {}",
+        if plan.known {
+            "already tracked, unfinished downloads would be resumed"
+        } else {
+            "new"
+        }
+    );
+    println!(
+        "Manifest:     {manifest_count} assets, {}",
+        HumanBytes(manifest_size)
+    );
+    println!(
+        "Download:     {} assets, {}",
+        downloads.len(),
+        HumanBytes(total_size(&downloads))
+    );
+    if !stored.is_empty() {
+        println!(
+            "Stored:       {} assets, {} (downloaded by an earlier check)",
+            stored.len(),
+            HumanBytes(total_size(&stored))
+        );
+    }
+    if mirror_filter.is_some() {
+        println!(
+            "Filtered:     {} assets, {} (recorded as not mirrored)",
+            skipped.len(),
+            HumanBytes(total_size(&skipped))
+        );
+    }
+    println!(
+        "Deduplicated: {} assets, {} saved (stored by {} earlier revisions)",
+        deduplicated.len(),
+        HumanBytes(deduplicated.iter().map(|asset| u64::from(asset.size)).sum()),
+        origins.len()
+    );
+
+    if list {
+        println!();
+        for asset in &downloads {
+            println!(
+                "{:>12}  {}",
+                HumanBytes(asset.size.into()).to_string(),
+                asset.file_name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn total_size(assets: &[Asset]) -> u64 {
+    assets.iter().map(|asset| u64::from(asset.size)).sum()
+}
@@ -0,0 +1,281 @@
+use crate::{
+    config::AppConfig,
+    db::{AssetRecord, Database},
+    fetcher::{
+        asset_fetcher::{AssetFetcher, SharedDownloads},
+        preflight::preflight,
+    },
+    revision::{Asset, Revision},
+    storage::{Storage, StorageBackend, StoredObject, asset_key, zstd_key},
+    wizard_patcher::WizardPatcher,
+};
+use indicatif::HumanBytes;
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+
+const MANIFESTS: [&str; 2] = ["LatestFileList.bin", "LatestFileList.xml"];
+
+/// Drift between the assets table and storage.
+#[derive(Debug, Default)]
+struct Drift {
+    /// Mirrored blobs that exist in neither representation
+    missing: Vec<(String, AssetRecord)>,
+    /// Uncompressed blobs whose size doesn't match the manifest, with the stored size
+    mismatched: Vec<(String, AssetRecord, u64)>,
+    /// Objects in revision directories that no row accounts for
+    orphaned: Vec<StoredObject>,
+    /// Leftovers of interrupted writes
+    stale: Vec<StoredObject>,
+    /// Stored WADs whose file table couldn't be read, with the keys they're stored under
+    unreadable: Vec<(String, AssetRecord, Vec<String>)>,
+}
+
+/// Compares the assets table against storage and reports missing, orphaned, partially written and damaged files.
+/// WADs whose file table couldn't be read count as damaged.
+///
+/// With `fix`, stray objects are deleted and missing or damaged blobs are downloaded again.
+/// Only meant to run while no fetch is in progress, as in-flight downloads look like stale `.part` files.
+pub async fn reconcile(config: &AppConfig, fix: bool) -> miette::Result<()> {
+    let db = Database::init(&config.database.path).await?;
+    let storage = Storage::from_config(config)?;
+
+    let revisions = db.list_revision_info().await?;
+    let records = db.list_blob_records().await?;
+    let objects = storage.list("").await?;
+    let object_count = objects.len();
+
+    let unreadable: HashSet<String> = db
+        .unreadable_wads()
+        .await?
+        .iter()
+        .map(|(revision, file_name)| asset_key(revision, file_name))
+        .collect();
+
+    let tracked: HashSet<&str> = revisions.iter().map(|r| r.name.as_str()).collect();
+    let drift = find_drift(&tracked, &unreadable, records, objects);
+
+    println!(
+        "Checked {} revisions against {object_count} stored objects",
+        revisions.len()
+    );
+    print_section(
+        "Missing",
+        drift
+            .missing
+            .iter()
+            .map(|(revision, record)| (asset_key(revision, &record.file_name), record.size as u64)),
+    );
+    print_section(
+        "Size mismatch",
+        drift.mismatched.iter().map(|(revision, record, stored)| {
+            (
+                format!(
+                    "{} (expected {})",
+                    asset_key(revision, &record.file_name),
+                    HumanBytes(record.size as u64)
+                ),
+                *stored,
+            )
+        }),
+    );
+    print_section(
+        "Unreadable WAD",
+        drift.unreadable.iter().map(|(revision, record, _)| {
+            (asset_key(revision, &record.file_name), record.size as u64)
+        }),
+    );
+    print_section(
+        "Orphaned",
+        drift
+            .orphaned
+            .iter()
+            .map(|object| (object.key.clone(), object.size)),
+    );
+    print_section(
+        "Stale .part",
+        drift
+            .stale
+            .iter()
+            .map(|object| (object.key.clone(), object.size)),
+    );
+
+    if !fix {
+        return Ok(());
+    }
+
+    let mut deleted = 0;
+    let stray_keys = drift
+        .orphaned
+        .iter()
+        .chain(&drift.stale)
+        .map(|object| object.key.clone())
+        .chain(
+            drift
+                .mismatched
+                .iter()
+                .map(|(revision, record, _)| asset_key(revision, &record.file_name)),
+        )
+        .chain(
+            drift
+                .unreadable
+                .iter()
+                .flat_map(|(_, _, keys)| keys.clone()),
+        );
+    for key in stray_keys {
+        match storage.delete(&key).await {
+            Ok(()) => deleted += 1,
+            Err(e) => warn!(key = %key, "couldn't delete object: {:?}", miette::Report::new(e)),
+        }
+    }
+    println!("Deleted {deleted} objects");
+
+    let mut requeue: HashMap<String, Vec<Asset>> = HashMap::new();
+    for (revision, record) in &drift.missing {
+        requeue
+            .entry(revision.clone())
+            .or_default()
+            .push(record.into());
+    }
+    for (revision, record, _) in &drift.mismatched {
+        requeue
+            .entry(revision.clone())
+            .or_default()
+            .push(record.into());
+    }
+    for (revision, record, _) in &drift.unreadable {
+        requeue
+            .entry(revision.clone())
+            .or_default()
+            .push(record.into());
+    }
+
+    let downloads = SharedDownloads::new(&config.fetcher);
+    for revision in &revisions {
+        let Some(assets) = requeue.remove(&revision.name) else {
+            continue;
+        };
+        let Some(url_prefix) = revision.url_prefix.clone() else {
+            warn!(
+                "Can't download {} assets of {} again, its URL prefix was never recorded",
+                assets.len(),
+                revision.name
+            );
+            continue;
+        };
+
+        preflight(&config.fetcher, &storage, &db, &revision.name, &assets).await?;
+
+        println!("Downloading {} assets of {}", assets.len(), revision.name);
+        let wizard_patcher = WizardPatcher {
+            list_file_url: String::new(),
+            url_prefix,
+            revision: Revision {
+                name: revision.name.clone(),
+                number: revision.number,
+            },
+        };
+        let asset_fetcher = AssetFetcher::new(
+            wizard_patcher,
+            &config.fetcher,
+            storage.clone(),
+            assets,
+            &downloads,
+        )?;
+
+        let report = asset_fetcher.fetch_assets().await?;
+        if !report.failed.is_empty() {
+            warn!(
+                "{} assets of {} still couldn't be downloaded",
+                report.failed.len(),
+                revision.name
+            );
+        }
+        db.record_sources(revision.name.clone(), report.served_by)
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn find_drift(
+    tracked: &HashSet<&str>,
+    unreadable: &HashSet<String>,
+    records: Vec<(String, AssetRecord)>,
+    objects: Vec<StoredObject>,
+) -> Drift {
+    let sizes: HashMap<String, u64> = objects
+        .iter()
+        .map(|object| (object.key.clone(), object.size))
+        .collect();
+
+    let mut expected: HashSet<String> = tracked
+        .iter()
+        .flat_map(|revision| MANIFESTS.map(|manifest| asset_key(revision, manifest)))
+        .collect();
+    let mut drift = Drift::default();
+
+    for (revision, record) in records {
+        let plain_key = asset_key(&revision, &record.file_name);
+        let compressed_key = zstd_key(&plain_key);
+        // The tar a blob was delivered in is kept next to it
+        if let Some(tar) = &record.tar_file_name {
+            expected.insert(asset_key(&revision, tar));
+        }
+
+        // Blobs that were filtered out may still have been stored before the filter changed, which is fine
+        if record.mirrored {
+            match (sizes.get(&plain_key), sizes.get(&compressed_key)) {
+                (None, None) => drift.missing.push((revision, record)),
+                // Only the uncompressed representation has a size that can be checked against the manifest
+                (Some(&stored), _) if stored != record.size as u64 => {
+                    drift.mismatched.push((revision, record, stored))
+                }
+                _ if unreadable.contains(&plain_key) => {
+                    let keys = [&plain_key, &compressed_key]
+                        .into_iter()
+                        .filter(|key| sizes.contains_key(*key))
+                        .cloned()
+                        .collect();
+                    drift.unreadable.push((revision, record, keys));
+                }
+                _ => {}
+            }
+        }
+
+        expected.insert(plain_key);
+        expected.insert(compressed_key);
+    }
+
+    for object in objects {
+        if object.key.ends_with(".part") {
+            drift.stale.push(object);
+            continue;
+        }
+
+        // Anything outside of revision directories (e.g. the database) isn't ours to judge
+        let directory = object.key.split('/').next().unwrap_or_default();
+        let in_revision = object.key.contains('/')
+            && (tracked.contains(directory)
+                || WizardPatcher::extract_revision_number(directory).is_ok());
+        if in_revision && !expected.contains(&object.key) {
+            drift.orphaned.push(object);
+        }
+    }
+
+    drift
+}
+
+fn print_section(title: &str, entries: impl Iterator<Item = (String, u64)>) {
+    let entries: Vec<(String, u64)> = entries.collect();
+    let bytes: u64 = entries.iter().map(|(_, size)| size).sum();
+
+    println!(
+        "{:<16}{} ({})",
+        format!("{title}:"),
+        entries.len(),
+        HumanBytes(bytes)
+    );
+    for (key, _) in entries {
+        println!("  - {key}");
+    }
+}
@@ -1,11 +1,40 @@
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
-use std::{net::SocketAddr, num::NonZeroUsize, path::Path};
+use std::{
+    net::SocketAddr,
+    num::{NonZeroU64, NonZeroUsize},
+    path::Path,
+};
 
 use crate::errors::ConfigError;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ServerConfig {
     pub endpoint: SocketAddr,
+    /// What to answer for assets the mirror filters left out, `not_found` if omitted
+    pub unmirrored: Option<UnmirroredAssets>,
+    /// What to do with known assets that aren't downloaded yet, `404` if omitted
+    pub fallback: Option<UpstreamFallback>,
+    /// Directory converted texture previews are cached in, `cache` if omitted
+    pub preview_cache: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamFallback {
+    /// Redirect the client to the patch server
+    Redirect,
+    /// Stream the asset from the patch server, caching it in storage on the way
+    Proxy,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmirroredAssets {
+    /// Answer with `404 Not Found` and explain that the asset isn't mirrored
+    NotFound,
+    /// Redirect the client to the patch server
+    Redirect,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -13,6 +42,113 @@ pub struct FetcherConfig {
     pub concurrent_downloads: NonZeroUsize,
     pub save_directory: String,
     pub fetch_interval: u64,
+    pub compression: Option<CompressionConfig>,
+    /// Combined download speed limit in bytes per second, proxied assets included
+    pub bandwidth_limit: Option<NonZeroU64>,
+    pub adaptive_concurrency: Option<AdaptiveConcurrencyConfig>,
+    /// Order in which new assets are downloaded, manifest order if omitted
+    pub download_order: Option<DownloadOrder>,
+    /// Which assets are mirrored at all, everything if omitted
+    pub filter: Option<MirrorFilterConfig>,
+    /// Alternative sources that are tried when the patch server fails or is slow
+    pub mirrors: Option<MirrorsConfig>,
+    /// Maximum size of the archive in bytes, counting every stored blob once
+    pub quota: Option<NonZeroU64>,
+    /// Index new WADs from their headers before they're downloaded, `false` if omitted
+    pub prefetch_wad_headers: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MirrorsConfig {
+    /// Base URLs laid out like Aurorium's file route, i.e. `{url}/{revision}/{file}`
+    pub urls: Vec<String>,
+    /// Order in which mirrors are tried, `ordered` if omitted
+    pub strategy: Option<MirrorStrategy>,
+    /// Seconds to wait for a source to start answering before trying the next one
+    pub slow_timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorStrategy {
+    /// In the order of `urls`
+    Ordered,
+    /// Most reliable and fastest mirror first
+    Health,
+}
+
+/// Assets left out by these filters are still tracked in the database, but never downloaded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MirrorFilterConfig {
+    /// Only mirror assets matching one of these globs
+    pub include: Option<Vec<String>>,
+    /// Never mirror assets matching one of these globs
+    pub exclude: Option<Vec<String>>,
+    /// Only mirror assets with one of these `FileType`s
+    pub file_types: Option<Vec<u32>>,
+    /// Never mirror assets with one of these `FileType`s
+    pub exclude_file_types: Option<Vec<u32>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum DownloadOrder {
+    /// Keep the order of `LatestFileList.xml`
+    Manifest,
+    SmallestFirst,
+    /// Executables, root-level files and `Root.wad` first
+    CriticalFirst,
+    /// Assets matching earlier globs first, unmatched assets last
+    Priority {
+        patterns: Vec<String>,
+    },
+}
+
+/// Lets the fetcher tune the number of parallel downloads itself, starting at `concurrent_downloads`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdaptiveConcurrencyConfig {
+    pub max_downloads: NonZeroUsize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompressionConfig {
+    /// zstd compression level (1-22), 3 is zstd's own default
+    pub level: i32,
+}
+
+/// Which revisions garbage collection keeps. A revision is kept if any rule matches, the latest one always is.
+///
+/// Without `keep_last` or `keep_since`, everything is kept.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RetentionConfig {
+    /// Keep the newest N revisions
+    pub keep_last: Option<NonZeroUsize>,
+    /// Keep revisions discovered on or after this date (`YYYY-MM-DD`)
+    pub keep_since: Option<NaiveDate>,
+    /// Revisions that are never removed
+    pub pinned: Option<Vec<String>>,
+    /// Collect garbage after every revision check, `false` if omitted
+    pub automatic: Option<bool>,
+}
+
+/// Follows another Aurorium instance instead of talking to the patch server.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReplicationConfig {
+    /// Base URL of the leader, e.g. `http://10.0.0.1:12369`
+    pub leader: String,
+    /// Seconds between polls of the leader, `fetcher.fetch_interval` if omitted
+    pub poll_interval: Option<u64>,
+}
+
+/// Decoding of game data files, see `?format=json` on WAD entries.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ObjectPropertyConfig {
+    /// JSON type dump of the client (wiztype/katsuba format, version 2)
+    pub types: String,
+    /// Decode data as shallow instead of deep, `false` if omitted
+    pub shallow: Option<bool>,
+    /// Property flags shallow data is written with, `24` if omitted
+    pub property_mask: Option<u32>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -32,12 +168,38 @@ pub struct DBConfig {
     pub path: String,
 }
 
+/// Where revisions are archived. Defaults to the local `fetcher.save_directory` if omitted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    Local,
+    S3(S3Config),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: Option<String>,
+    /// Custom endpoint for S3-compatible stores, e.g. `http://127.0.0.1:9000` for MinIO
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Key prefix inside the bucket, so several archives can share one
+    pub prefix: Option<String>,
+    pub allow_http: Option<bool>,
+    pub virtual_hosted_style: Option<bool>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub fetcher: FetcherConfig,
     pub patch: PatchConfig,
     pub database: DBConfig,
+    pub storage: Option<StorageConfig>,
+    pub replication: Option<ReplicationConfig>,
+    pub retention: Option<RetentionConfig>,
+    pub object_property: Option<ObjectPropertyConfig>,
     pub debug: Option<DebugConfig>,
 }
 
@@ -63,6 +225,9 @@ impl Default for AppConfig {
         AppConfig {
             server: ServerConfig {
                 endpoint: SocketAddr::from(([127, 0, 0, 1], 12369)),
+                unmirrored: None,
+                fallback: None,
+                preview_cache: None,
             },
             patch: PatchConfig {
                 host: "patch.us.wizard101.com".to_string(),
@@ -72,10 +237,22 @@ impl Default for AppConfig {
                 concurrent_downloads: unsafe { NonZeroUsize::new_unchecked(2) },
                 fetch_interval: 60 * 60 * 8,
                 save_directory: "data".to_string(),
+                compression: None,
+                bandwidth_limit: None,
+                adaptive_concurrency: None,
+                download_order: None,
+                filter: None,
+                mirrors: None,
+                quota: None,
+                prefetch_wad_headers: None,
             },
             database: DBConfig {
                 path: "aurorium.db".to_string(),
             },
+            storage: None,
+            replication: None,
+            retention: None,
+            object_property: None,
             debug: None,
         }
     }
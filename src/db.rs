@@ -1,16 +1,24 @@
 use crate::{
+    analytics::{DedupChain, DedupReport, FileChurn, GrowthReport, RevisionGrowth},
     errors::DbError,
+    localization::{LocaleString, StringDiff, StringSearchResults},
     revision::{Asset, Revision},
+    search::{Pattern, SearchFilter, SearchHit, SearchResults, fts_phrase},
+    wad::{WadArchive, WadDiff, WadEntry},
 };
 use async_sqlite::{Client, ClientBuilder, JournalMode};
-use rusqlite::{Connection, OptionalExtension, params};
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Statement, params};
 use rusqlite_migration::{M, Migrations};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::sync::LazyLock;
 use tracing::info;
 
 static MIGRATIONS: LazyLock<Migrations<'static>> = LazyLock::new(|| {
-    Migrations::new(vec![M::up(
-        "
+    Migrations::new(vec![
+        M::up(
+            "
             CREATE TABLE revisions (
                 revision_name TEXT NOT NULL PRIMARY KEY,
                 number INTEGER NOT NULL
@@ -34,9 +42,436 @@ static MIGRATIONS: LazyLock<Migrations<'static>> = LazyLock::new(|| {
 
             CREATE INDEX idx_assets_lookup ON assets (file_name, crc, size);
         ",
-    )])
+        ),
+        M::up(
+            "
+            ALTER TABLE revisions ADD COLUMN url_prefix TEXT;
+            ALTER TABLE assets ADD COLUMN mirrored INTEGER NOT NULL DEFAULT 1;
+        ",
+        ),
+        M::up(
+            "
+            ALTER TABLE assets ADD COLUMN source TEXT;
+        ",
+        ),
+        M::up(
+            "
+            ALTER TABLE revisions ADD COLUMN discovered_at TEXT;
+        ",
+        ),
+        M::up(
+            "
+            ALTER TABLE assets ADD COLUMN wad_version INTEGER;
+            ALTER TABLE assets ADD COLUMN wad_flags INTEGER;
+
+            CREATE TABLE wad_entries (
+                revision TEXT NOT NULL,
+                file_name TEXT NOT NULL,
+                name TEXT NOT NULL,
+                data_offset INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                compressed_size INTEGER NOT NULL,
+                compressed INTEGER NOT NULL,
+                crc INTEGER NOT NULL,
+
+                PRIMARY KEY (revision, file_name, name),
+                FOREIGN KEY (revision, file_name) REFERENCES assets(revision, file_name)
+            );
+        ",
+        ),
+        M::up(
+            "
+            CREATE TABLE string_tables (
+                revision TEXT NOT NULL,
+                file_name TEXT NOT NULL,
+                entry_name TEXT NOT NULL,
+                locale TEXT NOT NULL,
+                table_name TEXT NOT NULL,
+
+                PRIMARY KEY (revision, file_name, entry_name),
+                FOREIGN KEY (revision, file_name, entry_name) REFERENCES wad_entries(revision, file_name, name)
+                    ON UPDATE CASCADE ON DELETE CASCADE
+            );
+
+            CREATE TABLE locale_strings (
+                revision TEXT NOT NULL,
+                file_name TEXT NOT NULL,
+                entry_name TEXT NOT NULL,
+                key TEXT NOT NULL,
+                text TEXT NOT NULL,
+
+                PRIMARY KEY (revision, file_name, entry_name, key),
+                FOREIGN KEY (revision, file_name, entry_name) REFERENCES string_tables(revision, file_name, entry_name)
+                    ON UPDATE CASCADE ON DELETE CASCADE
+            );
+
+            CREATE INDEX idx_locale_strings_key ON locale_strings (key);
+        ",
+        ),
+        M::up(
+            "
+            -- Every asset and WAD entry name ever seen, `wad` is empty for assets
+            CREATE TABLE search_names (
+                id INTEGER PRIMARY KEY,
+                wad TEXT NOT NULL,
+                name TEXT NOT NULL,
+
+                UNIQUE (wad, name)
+            );
+
+            CREATE VIRTUAL TABLE search_index USING fts5 (
+                name,
+                content = 'search_names',
+                content_rowid = 'id',
+                tokenize = 'trigram'
+            );
+
+            -- Names are never removed, hits are resolved against the assets that still exist.
+            -- `NOT EXISTS` instead of `OR IGNORE`, as the conflict clause of the outer statement would override it
+            CREATE TRIGGER search_names_index AFTER INSERT ON search_names BEGIN
+                INSERT INTO search_index (rowid, name) VALUES (NEW.id, NEW.name);
+            END;
+
+            CREATE TRIGGER assets_search AFTER INSERT ON assets BEGIN
+                INSERT INTO search_names (wad, name)
+                SELECT '', NEW.file_name
+                WHERE NOT EXISTS (SELECT 1 FROM search_names WHERE wad = '' AND name = NEW.file_name);
+            END;
+
+            CREATE TRIGGER wad_entries_search AFTER INSERT ON wad_entries BEGIN
+                INSERT INTO search_names (wad, name)
+                SELECT NEW.file_name, NEW.name
+                WHERE NOT EXISTS (SELECT 1 FROM search_names WHERE wad = NEW.file_name AND name = NEW.name);
+            END;
+
+            INSERT INTO search_names (wad, name) SELECT DISTINCT '', file_name FROM assets;
+            INSERT INTO search_names (wad, name) SELECT DISTINCT file_name, name FROM wad_entries;
+
+            CREATE INDEX idx_wad_entries_name ON wad_entries (file_name, name);
+        ",
+        ),
+        M::up(
+            "
+            -- Why the file table of a WAD couldn't be read, it isn't retried until the WAD is downloaded again
+            ALTER TABLE assets ADD COLUMN wad_error TEXT;
+        ",
+        ),
+        M::up(
+            "
+            -- Keys and texts of every string, for substring searches within a revision
+            CREATE VIRTUAL TABLE strings_index USING fts5 (
+                key,
+                text,
+                content = 'locale_strings',
+                tokenize = 'trigram'
+            );
+
+            -- Also fired by the cascades from `string_tables`, which is how strings are replaced and moved
+            CREATE TRIGGER locale_strings_index AFTER INSERT ON locale_strings BEGIN
+                INSERT INTO strings_index (rowid, key, text) VALUES (NEW.rowid, NEW.key, NEW.text);
+            END;
+
+            CREATE TRIGGER locale_strings_unindex AFTER DELETE ON locale_strings BEGIN
+                INSERT INTO strings_index (strings_index, rowid, key, text)
+                VALUES ('delete', OLD.rowid, OLD.key, OLD.text);
+            END;
+
+            CREATE TRIGGER locale_strings_reindex AFTER UPDATE OF key, text ON locale_strings BEGIN
+                INSERT INTO strings_index (strings_index, rowid, key, text)
+                VALUES ('delete', OLD.rowid, OLD.key, OLD.text);
+                INSERT INTO strings_index (rowid, key, text) VALUES (NEW.rowid, NEW.key, NEW.text);
+            END;
+
+            INSERT INTO strings_index (strings_index) VALUES ('rebuild');
+        ",
+        ),
+        M::up(
+            "
+            -- Why the strings of a `.lang` entry couldn't be extracted, it isn't retried until the WAD is downloaded again
+            ALTER TABLE wad_entries ADD COLUMN string_error TEXT;
+        ",
+        ),
+    ])
 });
 
+/// Finds the revision that already stores an identical blob, matched by name, CRC and size.
+const ORIGIN_LOOKUP: &str =
+    "SELECT origin_revision FROM assets WHERE file_name = ?1 AND crc = ?2 AND size = ?3 LIMIT 1";
+
+/// Resolves the origin revision of an asset of `revision_name`, and whether it has to be downloaded.
+///
+/// Assets whose origin is the revision itself are queued again, so interrupted downloads get resumed.
+fn resolve_origin(
+    stmt_check: &mut Statement,
+    revision_name: &str,
+    asset: &Asset,
+) -> rusqlite::Result<(String, bool)> {
+    let existing_origin: Option<String> = stmt_check
+        .query_row(params![asset.file_name, asset.crc, asset.size], |row| {
+            row.get(0)
+        })
+        .optional()?;
+
+    Ok(match existing_origin {
+        Some(origin) => {
+            let download = origin == revision_name;
+            (origin, download)
+        }
+        None => (revision_name.to_string(), true),
+    })
+}
+
+/// What `insert_new_revision` would do with a manifest, computed without writing anything.
+#[derive(Debug, Default)]
+pub struct RevisionPlan {
+    /// Whether the revision is already tracked
+    pub known: bool,
+    /// Assets that would be queued for download
+    pub queued: Vec<Asset>,
+    /// Assets whose blob is already stored by another revision, with that revision
+    pub deduplicated: Vec<(Asset, String)>,
+}
+
+/// A tracked revision, as seen by the retention policy.
+#[derive(Debug, Clone)]
+pub struct RevisionInfo {
+    pub name: String,
+    pub number: i64,
+    /// `None` for revisions tracked before discovery times were recorded
+    pub discovered_at: Option<NaiveDateTime>,
+    pub url_prefix: Option<String>,
+}
+
+/// A blob stored by a revision that is about to be removed, but still referenced by a retained one.
+#[derive(Debug, Clone)]
+pub struct BlobMove {
+    pub file_name: String,
+    pub from: String,
+    /// The oldest retained revision referencing the blob, which becomes its new origin
+    pub to: String,
+    /// Tar the blob was delivered in, which moves along with it
+    pub tar_file_name: Option<String>,
+}
+
+/// What removing a set of revisions does to the blobs they store.
+#[derive(Debug, Default)]
+pub struct GcPlan {
+    pub moves: Vec<BlobMove>,
+    /// Revision, file name and size of blobs nothing retained refers to anymore
+    pub unreferenced: Vec<(String, String, u32)>,
+}
+
+/// An entry of an indexed WAD, along with where the WAD is stored.
+#[derive(Debug, Clone)]
+pub struct WadEntryLocation {
+    pub origin_revision: String,
+    /// Path of the WAD within its revision
+    pub file_name: String,
+    pub entry: WadEntry,
+}
+
+/// Where an asset of a revision is actually stored, and its uncompressed size.
+#[derive(Debug, Clone)]
+pub struct AssetLocation {
+    pub origin_revision: String,
+    pub size: u32,
+    pub crc: u32,
+    /// Whether the blob was (or will be) downloaded, or was left out by the mirror filters
+    pub mirrored: bool,
+    /// `url_prefix` of the origin revision on the patch server, if known. Always `None` for assets packed into a
+    /// tar, upstream has no copy of them to point clients at
+    pub url_prefix: Option<String>,
+}
+
+/// Where a tar that upstream packs assets into is stored.
+#[derive(Debug, Clone)]
+pub struct TarLocation {
+    pub origin_revision: String,
+    /// `url_prefix` of the origin revision on the patch server, if known
+    pub url_prefix: Option<String>,
+}
+
+/// A revision with all of its asset rows, as served by `/metadata/{revision}` to replication followers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionMetadata {
+    pub name: String,
+    pub number: i64,
+    pub url_prefix: Option<String>,
+    /// UTC timestamp (`YYYY-MM-DD HH:MM:SS`) of when the leader first saw the revision
+    #[serde(default)]
+    pub discovered_at: Option<String>,
+    pub assets: Vec<AssetRecord>,
+}
+
+/// A row of the `assets` table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetRecord {
+    pub file_name: String,
+    pub tar_file_name: Option<String>,
+    pub file_type: u32,
+    pub size: u32,
+    pub crc: u32,
+    pub header_crc: u32,
+    pub header_size: u32,
+    pub compressed_header_size: u32,
+    pub origin_revision: String,
+    pub mirrored: bool,
+    pub source: Option<String>,
+}
+
+/// Matches `a.file_name` against `?2`, either the full path of a WAD or just its file name.
+const WAD_NAME_MATCH: &str =
+    "(a.file_name = ?2 OR substr(a.file_name, -length(?2) - 1) = '/' || ?2)";
+
+/// Loads the file table of a WAD of `revision_name` from its origin row, along with the WAD's path.
+fn load_wad_index(
+    conn: &Connection,
+    revision_name: &str,
+    wad_name: &str,
+) -> rusqlite::Result<Option<(String, WadArchive)>> {
+    let header = conn
+        .query_row(
+            &format!(
+                "SELECT o.revision, o.file_name, o.wad_version, o.wad_flags
+                FROM assets a JOIN assets o ON o.revision = a.origin_revision AND o.file_name = a.file_name
+                WHERE a.revision = ?1 AND {WAD_NAME_MATCH} AND o.wad_version IS NOT NULL
+                ORDER BY a.file_name = ?2 DESC, a.file_name LIMIT 1"
+            ),
+            params![revision_name, wad_name],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((origin, file_name, version, flags)) = header else {
+        return Ok(None);
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT name, data_offset, size, compressed_size, compressed, crc FROM wad_entries
+        WHERE revision = ?1 AND file_name = ?2 ORDER BY data_offset",
+    )?;
+    let entries = stmt
+        .query_map(params![origin, file_name], |row| {
+            Ok(WadEntry {
+                name: row.get(0)?,
+                offset: row.get(1)?,
+                size: row.get(2)?,
+                compressed_size: row.get(3)?,
+                compressed: row.get(4)?,
+                crc: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Some((
+        file_name,
+        WadArchive {
+            version,
+            flags,
+            entries,
+        },
+    )))
+}
+
+/// Number of `revision_name`, `None` if it isn't tracked.
+fn revision_number(conn: &Connection, revision_name: &str) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT number FROM revisions WHERE revision_name = ?1",
+        params![revision_name],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Loads the strings of `revision_name` from the string tables of its WADs, optionally only one key or locale.
+///
+/// `None` if the revision isn't tracked.
+fn load_strings(
+    conn: &Connection,
+    revision_name: &str,
+    key: Option<&str>,
+    locale: Option<&str>,
+) -> rusqlite::Result<Option<Vec<LocaleString>>> {
+    let known = conn
+        .query_row(
+            "SELECT 1 FROM revisions WHERE revision_name = ?1",
+            params![revision_name],
+            |_| Ok(()),
+        )
+        .optional()?;
+    if known.is_none() {
+        return Ok(None);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT t.locale, t.table_name, s.key, s.text, s.revision
+        FROM assets a
+        JOIN string_tables t ON t.revision = a.origin_revision AND t.file_name = a.file_name
+        JOIN locale_strings s ON s.revision = t.revision AND s.file_name = t.file_name
+            AND s.entry_name = t.entry_name
+        WHERE a.revision = ?1 AND (?2 IS NULL OR s.key = ?2) AND (?3 IS NULL OR t.locale = ?3)
+        ORDER BY t.locale, t.table_name, s.key",
+    )?;
+    let strings = stmt
+        .query_map(params![revision_name, key, locale], locale_string)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Some(strings))
+}
+
+/// Reads a string selected as locale, table name, key, text and revision.
+fn locale_string(row: &rusqlite::Row) -> rusqlite::Result<LocaleString> {
+    Ok(LocaleString {
+        locale: row.get(0)?,
+        table: row.get(1)?,
+        key: row.get(2)?,
+        text: row.get(3)?,
+        revision: row.get(4)?,
+    })
+}
+
+const ASSET_RECORD_COLUMNS: &str =
+    "file_name, tar_file_name, file_type, size, crc, header_crc, header_size,
+    compressed_header_size, origin_revision, mirrored, source";
+
+/// Maps a row selected with `ASSET_RECORD_COLUMNS`.
+fn asset_record(row: &rusqlite::Row) -> rusqlite::Result<AssetRecord> {
+    Ok(AssetRecord {
+        file_name: row.get(0)?,
+        tar_file_name: row.get(1)?,
+        file_type: row.get(2)?,
+        size: row.get(3)?,
+        crc: row.get(4)?,
+        header_crc: row.get(5)?,
+        header_size: row.get(6)?,
+        compressed_header_size: row.get(7)?,
+        origin_revision: row.get(8)?,
+        mirrored: row.get(9)?,
+        source: row.get(10)?,
+    })
+}
+
+impl From<&AssetRecord> for Asset {
+    fn from(record: &AssetRecord) -> Self {
+        Asset {
+            file_name: record.file_name.clone(),
+            tar_file_name: record.tar_file_name.clone(),
+            file_type: record.file_type,
+            size: record.size,
+            header_size: record.header_size,
+            compressed_header_size: record.compressed_header_size,
+            crc: record.crc,
+            header_crc: record.header_crc,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Database {
     client: Client,
@@ -64,6 +499,18 @@ impl Database {
         Ok(Self { client })
     }
 
+    /// Opens an existing database without migrating or writing to it, e.g. for planning runs.
+    pub async fn open_read_only(path: &str) -> miette::Result<Self> {
+        let client = ClientBuilder::new()
+            .path(path)
+            .flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+            .open()
+            .await
+            .map_err(DbError::AsyncSqlite)?;
+
+        Ok(Self { client })
+    }
+
     pub async fn get_latest_revision(&self) -> miette::Result<Option<Revision>> {
         let revision = self
             .client
@@ -127,6 +574,7 @@ impl Database {
     pub async fn insert_new_revision(
         &self,
         revision: Revision,
+        url_prefix: Option<String>,
         fetched_assets: Vec<Asset>,
     ) -> miette::Result<Vec<Asset>> {
         let assets_to_download = self
@@ -135,13 +583,12 @@ impl Database {
             let tx = conn.transaction().map_err(DbError::Transaction)?;
 
             tx.execute(
-                "INSERT OR IGNORE INTO revisions (revision_name, number) VALUES (?1, ?2)",
-                params![revision.name, revision.number],
+                "INSERT INTO revisions (revision_name, number, url_prefix, discovered_at) VALUES (?1, ?2, ?3, datetime('now'))
+                ON CONFLICT (revision_name) DO UPDATE SET url_prefix = COALESCE(excluded.url_prefix, url_prefix)",
+                params![revision.name, revision.number, url_prefix],
             )?;
 
-            let mut stmt_check = tx.prepare(
-                "SELECT origin_revision FROM assets WHERE file_name = ?1 AND crc = ?2 AND size = ?3 LIMIT 1"
-            )?;
+            let mut stmt_check = tx.prepare(ORIGIN_LOOKUP)?;
             let mut stmt_insert = tx.prepare(
                 "INSERT OR IGNORE INTO assets (
                     revision, file_name, tar_file_name, file_type, size, crc,
@@ -152,22 +599,10 @@ impl Database {
             let mut assets_to_download = Vec::new();
 
             for asset in fetched_assets {
-                let existing_origin: Option<String> = stmt_check
-                    .query_row(params![asset.file_name, asset.crc, asset.size], |row| row.get(0))
-                    .optional()?;
-
-                let origin_revision = match existing_origin {
-                    Some(origin) => {
-                        if origin == revision.name {
-                            assets_to_download.push(asset.clone());
-                        }
-                        origin
-                    }
-                    None => {
-                        assets_to_download.push(asset.clone());
-                        revision.name.clone()
-                    }
-                };
+                let (origin_revision, download) = resolve_origin(&mut stmt_check, &revision.name, &asset)?;
+                if download {
+                    assets_to_download.push(asset.clone());
+                }
 
                 stmt_insert.execute(params![
                     revision.name,
@@ -194,22 +629,132 @@ impl Database {
         Ok(assets_to_download)
     }
 
+    /// Computes which assets `insert_new_revision` would queue for a manifest, using the same deduplication.
+    pub async fn plan_new_revision(
+        &self,
+        revision: Revision,
+        fetched_assets: Vec<Asset>,
+    ) -> miette::Result<RevisionPlan> {
+        let plan = self
+            .client
+            .conn_and_then(move |conn| -> Result<RevisionPlan, DbError> {
+                let known = conn
+                    .query_row(
+                        "SELECT 1 FROM revisions WHERE revision_name = ?1",
+                        params![revision.name],
+                        |_| Ok(()),
+                    )
+                    .optional()?
+                    .is_some();
+
+                let mut stmt_check = conn.prepare(ORIGIN_LOOKUP)?;
+                let mut plan = RevisionPlan {
+                    known,
+                    ..Default::default()
+                };
+
+                for asset in fetched_assets {
+                    match resolve_origin(&mut stmt_check, &revision.name, &asset)? {
+                        (_, true) => plan.queued.push(asset),
+                        (origin, false) => plan.deduplicated.push((asset, origin)),
+                    }
+                }
+
+                Ok(plan)
+            })
+            .await?;
+
+        Ok(plan)
+    }
+
+    /// Marks the assets a revision introduced as mirrored or left out by the mirror filters.
+    pub async fn set_mirrored(
+        &self,
+        revision_name: String,
+        file_names: Vec<String>,
+        mirrored: bool,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_mut_and_then(move |conn: &mut Connection| -> Result<(), DbError> {
+                let tx = conn.transaction().map_err(DbError::Transaction)?;
+
+                let mut stmt = tx.prepare(
+                    "UPDATE assets SET mirrored = ?1 WHERE revision = ?2 AND file_name = ?3 AND origin_revision = revision",
+                )?;
+                for file_name in file_names {
+                    stmt.execute(params![mirrored, revision_name, file_name])?;
+                }
+
+                drop(stmt);
+                tx.commit().map_err(DbError::Transaction)?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records which base URL (patch server or mirror) served each asset a revision introduced.
+    /// WADs among them get another chance at being indexed, and at having their string tables extracted.
+    pub async fn record_sources(
+        &self,
+        revision_name: String,
+        sources: Vec<(String, String)>,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_mut_and_then(move |conn: &mut Connection| -> Result<(), DbError> {
+                let tx = conn.transaction().map_err(DbError::Transaction)?;
+
+                let mut stmt = tx.prepare(
+                    "UPDATE assets SET source = ?1, wad_error = NULL WHERE revision = ?2 AND file_name = ?3",
+                )?;
+                let mut stmt_entries = tx.prepare(
+                    "UPDATE wad_entries SET string_error = NULL
+                    WHERE revision = ?1 AND file_name = ?2 AND string_error IS NOT NULL",
+                )?;
+                for (file_name, source) in sources {
+                    stmt.execute(params![source, revision_name, file_name])?;
+                    stmt_entries.execute(params![revision_name, file_name])?;
+                }
+
+                drop(stmt);
+                drop(stmt_entries);
+                tx.commit().map_err(DbError::Transaction)?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn get_revision_for_asset(
         &self,
         revision_name: String,
         file_name: String,
-    ) -> Result<Option<String>, DbError> {
+    ) -> Result<Option<AssetLocation>, DbError> {
         let result = self
             .client
-            .conn_and_then(move |conn| -> Result<Option<String>, DbError> {
+            .conn_and_then(move |conn| -> Result<Option<AssetLocation>, DbError> {
+                // The row of the origin revision is the one that knows whether the blob was mirrored
                 let mut stmt = conn.prepare(
-                    "SELECT origin_revision FROM assets WHERE revision = ?1 AND file_name = ?2 LIMIT 1",
+                    "SELECT a.origin_revision, a.size, a.crc, o.mirrored,
+                        CASE WHEN a.tar_file_name IS NULL OR a.tar_file_name = a.file_name
+                            THEN r.url_prefix END
+                    FROM assets a
+                    JOIN assets o ON o.revision = a.origin_revision AND o.file_name = a.file_name
+                    JOIN revisions r ON r.revision_name = a.origin_revision
+                    WHERE a.revision = ?1 AND a.file_name = ?2 LIMIT 1",
                 )?;
 
                 let asset_info = stmt
                     .query_row(params![revision_name, file_name], |row| {
-                        let origin_revision: String = row.get(0)?;
-                        Ok(origin_revision)
+                        Ok(AssetLocation {
+                            origin_revision: row.get(0)?,
+                            size: row.get(1)?,
+                            crc: row.get(2)?,
+                            mirrored: row.get(3)?,
+                            url_prefix: row.get(4)?,
+                        })
                     })
                     .optional()?;
 
@@ -219,4 +764,1004 @@ impl Database {
 
         Ok(result)
     }
+
+    /// Where the tar `tar_file_name` that assets of `revision_name` are packed into is stored.
+    ///
+    /// Tars are stored by the revisions that downloaded them, the most recent one holds the current contents.
+    pub async fn get_revision_for_tar(
+        &self,
+        revision_name: String,
+        tar_file_name: String,
+    ) -> Result<Option<TarLocation>, DbError> {
+        let result = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<TarLocation>, DbError> {
+                let location = conn
+                    .query_row(
+                        "SELECT a.origin_revision, r.url_prefix
+                        FROM assets a
+                        JOIN assets o ON o.revision = a.origin_revision AND o.file_name = a.file_name
+                        JOIN revisions r ON r.revision_name = a.origin_revision
+                        WHERE a.revision = ?1 AND a.tar_file_name = ?2 AND o.mirrored = 1
+                        ORDER BY r.number DESC LIMIT 1",
+                        params![revision_name, tar_file_name],
+                        |row| {
+                            Ok(TarLocation {
+                                origin_revision: row.get(0)?,
+                                url_prefix: row.get(1)?,
+                            })
+                        },
+                    )
+                    .optional()?;
+
+                Ok(location)
+            })
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_revision_metadata(
+        &self,
+        revision_name: String,
+    ) -> Result<Option<RevisionMetadata>, DbError> {
+        let result = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<RevisionMetadata>, DbError> {
+                let revision = conn
+                    .query_row(
+                        "SELECT number, url_prefix, discovered_at FROM revisions WHERE revision_name = ?1",
+                        params![revision_name],
+                        |row| {
+                            Ok((
+                                row.get::<_, i64>(0)?,
+                                row.get::<_, Option<String>>(1)?,
+                                row.get::<_, Option<String>>(2)?,
+                            ))
+                        },
+                    )
+                    .optional()?;
+
+                let Some((number, url_prefix, discovered_at)) = revision else {
+                    return Ok(None);
+                };
+
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {ASSET_RECORD_COLUMNS} FROM assets WHERE revision = ?1 ORDER BY rowid"
+                ))?;
+                let assets = stmt
+                    .query_map(params![revision_name], asset_record)?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Some(RevisionMetadata {
+                    name: revision_name,
+                    number,
+                    url_prefix,
+                    discovered_at,
+                    assets,
+                }))
+            })
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Looks up the revision storing each blob, matched by name, CRC and size like deduplication does.
+    pub async fn find_origins(
+        &self,
+        blobs: Vec<(String, u32, u32)>,
+    ) -> miette::Result<Vec<Option<String>>> {
+        let origins = self
+            .client
+            .conn_and_then(move |conn| -> Result<Vec<Option<String>>, DbError> {
+                let mut stmt = conn.prepare(ORIGIN_LOOKUP)?;
+                let origins = blobs
+                    .iter()
+                    .map(|(file_name, crc, size)| {
+                        stmt.query_row(params![file_name, crc, size], |row| row.get(0))
+                            .optional()
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(origins)
+            })
+            .await?;
+
+        Ok(origins)
+    }
+
+    /// Inserts a revision exactly as the leader recorded it, without deduplicating on our own.
+    ///
+    /// Origin revisions have to be replicated first, so revisions must be inserted in ascending order.
+    pub async fn insert_replicated_revision(
+        &self,
+        metadata: RevisionMetadata,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_mut_and_then(move |conn: &mut Connection| -> Result<(), DbError> {
+                let tx = conn.transaction().map_err(DbError::Transaction)?;
+
+                tx.execute(
+                    "INSERT INTO revisions (revision_name, number, url_prefix, discovered_at)
+                    VALUES (?1, ?2, ?3, COALESCE(?4, datetime('now')))
+                    ON CONFLICT (revision_name) DO UPDATE SET
+                        url_prefix = excluded.url_prefix, discovered_at = excluded.discovered_at",
+                    params![
+                        metadata.name,
+                        metadata.number,
+                        metadata.url_prefix,
+                        metadata.discovered_at
+                    ],
+                )?;
+
+                let mut stmt = tx.prepare(
+                    "INSERT INTO assets (
+                        revision, file_name, tar_file_name, file_type, size, crc, header_crc,
+                        header_size, compressed_header_size, origin_revision, mirrored, source
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                    ON CONFLICT (revision, file_name) DO UPDATE SET
+                        origin_revision = excluded.origin_revision,
+                        mirrored = excluded.mirrored, source = excluded.source",
+                )?;
+                for asset in &metadata.assets {
+                    stmt.execute(params![
+                        metadata.name,
+                        asset.file_name,
+                        asset.tar_file_name,
+                        asset.file_type,
+                        asset.size,
+                        asset.crc,
+                        asset.header_crc,
+                        asset.header_size,
+                        asset.compressed_header_size,
+                        asset.origin_revision,
+                        asset.mirrored,
+                        asset.source
+                    ])?;
+                }
+
+                drop(stmt);
+                tx.commit().map_err(DbError::Transaction)?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Bytes of all mirrored blobs, counting each blob once. Blobs introduced by `exclude_revision` are left out.
+    pub async fn stored_bytes(&self, exclude_revision: String) -> Result<u64, DbError> {
+        let bytes = self
+            .client
+            .conn_and_then(move |conn| -> Result<u64, DbError> {
+                let bytes: i64 = conn.query_row(
+                    "SELECT COALESCE(SUM(size), 0) FROM assets
+                    WHERE origin_revision = revision AND mirrored = 1 AND revision != ?1",
+                    params![exclude_revision],
+                    |row| row.get(0),
+                )?;
+                Ok(bytes as u64)
+            })
+            .await?;
+
+        Ok(bytes)
+    }
+
+    pub async fn list_revision_info(&self) -> miette::Result<Vec<RevisionInfo>> {
+        let revisions = self
+            .client
+            .conn_and_then(|conn| -> Result<Vec<RevisionInfo>, DbError> {
+                let mut stmt = conn.prepare(
+                    "SELECT revision_name, number, discovered_at, url_prefix FROM revisions ORDER BY number DESC",
+                )?;
+                let revisions = stmt
+                    .query_map([], |row| {
+                        let discovered_at: Option<String> = row.get(2)?;
+                        Ok(RevisionInfo {
+                            name: row.get(0)?,
+                            number: row.get(1)?,
+                            discovered_at: discovered_at.and_then(|date| {
+                                NaiveDateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S").ok()
+                            }),
+                            url_prefix: row.get(3)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(revisions)
+            })
+            .await?;
+
+        Ok(revisions)
+    }
+
+    /// Works out which blobs of `removed` revisions have to move to a retained revision, and which can be deleted.
+    pub async fn plan_garbage_collection(&self, removed: Vec<String>) -> miette::Result<GcPlan> {
+        let plan = self
+            .client
+            .conn_and_then(move |conn| -> Result<GcPlan, DbError> {
+                let removed_set: HashSet<&str> = removed.iter().map(String::as_str).collect();
+
+                let mut stmt_blobs = conn.prepare(
+                    "SELECT file_name, size, tar_file_name FROM assets
+                    WHERE revision = ?1 AND origin_revision = revision",
+                )?;
+                let mut stmt_referrers = conn.prepare(
+                    "SELECT a.revision FROM assets a
+                    JOIN revisions r ON r.revision_name = a.revision
+                    WHERE a.origin_revision = ?1 AND a.file_name = ?2
+                    ORDER BY r.number ASC",
+                )?;
+
+                let mut plan = GcPlan::default();
+                for revision in &removed {
+                    let blobs = stmt_blobs
+                        .query_map(params![revision], |row| {
+                            Ok((
+                                row.get::<_, String>(0)?,
+                                row.get::<_, u32>(1)?,
+                                row.get::<_, Option<String>>(2)?,
+                            ))
+                        })?
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    for (file_name, size, tar_file_name) in blobs {
+                        let referrers = stmt_referrers
+                            .query_map(params![revision, file_name], |row| row.get::<_, String>(0))?
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                        match referrers
+                            .into_iter()
+                            .find(|referrer| !removed_set.contains(referrer.as_str()))
+                        {
+                            Some(to) => plan.moves.push(BlobMove {
+                                file_name,
+                                from: revision.clone(),
+                                to,
+                                tar_file_name,
+                            }),
+                            None => plan.unreferenced.push((revision.clone(), file_name, size)),
+                        }
+                    }
+                }
+
+                Ok(plan)
+            })
+            .await?;
+
+        Ok(plan)
+    }
+
+    /// Deletes `removed` revisions with all of their rows, after making the targets of `moves` the new origins.
+    pub async fn remove_revisions(
+        &self,
+        removed: Vec<String>,
+        moves: Vec<BlobMove>,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_mut_and_then(move |conn: &mut Connection| -> Result<(), DbError> {
+                let tx = conn.transaction().map_err(DbError::Transaction)?;
+
+                {
+                    // The new origin row takes over what the old one knew about the blob
+                    let mut stmt_adopt = tx.prepare(
+                        "UPDATE assets SET
+                            mirrored = (SELECT mirrored FROM assets WHERE revision = ?1 AND file_name = ?3),
+                            source = (SELECT source FROM assets WHERE revision = ?1 AND file_name = ?3),
+                            wad_version = (SELECT wad_version FROM assets WHERE revision = ?1 AND file_name = ?3),
+                            wad_flags = (SELECT wad_flags FROM assets WHERE revision = ?1 AND file_name = ?3),
+                            wad_error = (SELECT wad_error FROM assets WHERE revision = ?1 AND file_name = ?3)
+                        WHERE revision = ?2 AND file_name = ?3",
+                    )?;
+                    let mut stmt_repoint = tx.prepare(
+                        "UPDATE assets SET origin_revision = ?2 WHERE origin_revision = ?1 AND file_name = ?3",
+                    )?;
+                    let mut stmt_entries = tx.prepare(
+                        "UPDATE wad_entries SET revision = ?2 WHERE revision = ?1 AND file_name = ?3",
+                    )?;
+                    for BlobMove {
+                        file_name, from, to, ..
+                    } in &moves
+                    {
+                        stmt_adopt.execute(params![from, to, file_name])?;
+                        stmt_repoint.execute(params![from, to, file_name])?;
+                        stmt_entries.execute(params![from, to, file_name])?;
+                    }
+
+                    let mut stmt_wad_entries =
+                        tx.prepare("DELETE FROM wad_entries WHERE revision = ?1")?;
+                    let mut stmt_assets = tx.prepare("DELETE FROM assets WHERE revision = ?1")?;
+                    let mut stmt_revision =
+                        tx.prepare("DELETE FROM revisions WHERE revision_name = ?1")?;
+                    // Removed revisions may still point at each other, so all asset rows go first
+                    for revision in &removed {
+                        stmt_wad_entries.execute(params![revision])?;
+                        stmt_assets.execute(params![revision])?;
+                    }
+                    for revision in &removed {
+                        stmt_revision.execute(params![revision])?;
+                    }
+                }
+
+                tx.commit().map_err(DbError::Transaction)?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Every row that stores a blob (its origin is its own revision), grouped by revision.
+    pub async fn list_blob_records(&self) -> miette::Result<Vec<(String, AssetRecord)>> {
+        let records = self
+            .client
+            .conn_and_then(|conn| -> Result<Vec<(String, AssetRecord)>, DbError> {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {ASSET_RECORD_COLUMNS}, revision FROM assets
+                    WHERE origin_revision = revision ORDER BY revision, rowid"
+                ))?;
+                let records = stmt
+                    .query_map([], |row| Ok((row.get(11)?, asset_record(row)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(records)
+            })
+            .await?;
+
+        Ok(records)
+    }
+
+    /// Blob rows the mirror filter left out, grouped by revision.
+    pub async fn list_unmirrored_records(&self) -> miette::Result<Vec<(String, AssetRecord)>> {
+        let records = self
+            .client
+            .conn_and_then(|conn| -> Result<Vec<(String, AssetRecord)>, DbError> {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {ASSET_RECORD_COLUMNS}, revision FROM assets
+                    WHERE origin_revision = revision AND mirrored = 0 ORDER BY revision, rowid"
+                ))?;
+                let records = stmt
+                    .query_map([], |row| Ok((row.get(11)?, asset_record(row)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(records)
+            })
+            .await?;
+
+        Ok(records)
+    }
+
+    /// Downloaded WADs whose file table hasn't been indexed yet, as `(revision, file_name)`.
+    pub async fn unindexed_wads(&self) -> miette::Result<Vec<(String, String)>> {
+        let wads = self
+            .client
+            .conn_and_then(|conn| -> Result<Vec<(String, String)>, DbError> {
+                let mut stmt = conn.prepare(
+                    "SELECT revision, file_name FROM assets
+                    WHERE origin_revision = revision AND mirrored = 1 AND wad_version IS NULL
+                        AND wad_error IS NULL AND file_name LIKE '%.wad'
+                    ORDER BY revision, rowid",
+                )?;
+                let wads = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(wads)
+            })
+            .await?;
+
+        Ok(wads)
+    }
+
+    /// Remembers that the file table of the WAD `file_name` of `revision` can't be read, so it isn't tried again until
+    /// the WAD is downloaded again.
+    pub async fn mark_wad_unreadable(
+        &self,
+        revision_name: String,
+        file_name: String,
+        error: String,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_and_then(move |conn| -> Result<(), DbError> {
+                conn.execute(
+                    "UPDATE assets SET wad_error = ?3 WHERE revision = ?1 AND file_name = ?2",
+                    params![revision_name, file_name, error],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// WADs whose file table couldn't be read, as `(revision, file_name)`.
+    pub async fn unreadable_wads(&self) -> miette::Result<Vec<(String, String)>> {
+        let wads = self
+            .client
+            .conn_and_then(|conn| -> Result<Vec<(String, String)>, DbError> {
+                let mut stmt = conn.prepare(
+                    "SELECT revision, file_name FROM assets
+                    WHERE origin_revision = revision AND wad_error IS NOT NULL
+                    ORDER BY revision, rowid",
+                )?;
+                let wads = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(wads)
+            })
+            .await?;
+
+        Ok(wads)
+    }
+
+    /// Replaces the entries stored for the WAD `file_name` of `revision`, which has to be the WAD's origin row.
+    pub async fn save_wad_index(
+        &self,
+        revision_name: String,
+        file_name: String,
+        archive: WadArchive,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_mut_and_then(move |conn: &mut Connection| -> Result<(), DbError> {
+                let tx = conn.transaction().map_err(DbError::Transaction)?;
+
+                tx.execute(
+                    "DELETE FROM wad_entries WHERE revision = ?1 AND file_name = ?2",
+                    params![revision_name, file_name],
+                )?;
+                {
+                    let mut stmt_insert = tx.prepare(
+                        "INSERT OR REPLACE INTO wad_entries (
+                            revision, file_name, name, data_offset, size, compressed_size, compressed, crc
+                        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    )?;
+                    for entry in &archive.entries {
+                        stmt_insert.execute(params![
+                            revision_name,
+                            file_name,
+                            entry.name,
+                            entry.offset,
+                            entry.size,
+                            entry.compressed_size,
+                            entry.compressed,
+                            entry.crc
+                        ])?;
+                    }
+                }
+                tx.execute(
+                    "UPDATE assets SET wad_version = ?3, wad_flags = ?4, wad_error = NULL
+                    WHERE revision = ?1 AND file_name = ?2",
+                    params![revision_name, file_name, archive.version, archive.flags],
+                )?;
+
+                tx.commit().map_err(DbError::Transaction)?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// The indexed file table of a WAD of `revision`, looked up through its origin revision.
+    ///
+    /// `wad_name` is matched like in `get_wad_entry`. `None` if the revision has no such WAD or it wasn't indexed (yet).
+    pub async fn get_wad_index(
+        &self,
+        revision_name: String,
+        wad_name: String,
+    ) -> Result<Option<WadArchive>, DbError> {
+        let archive = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<WadArchive>, DbError> {
+                Ok(load_wad_index(conn, &revision_name, &wad_name)?.map(|(_, archive)| archive))
+            })
+            .await?;
+
+        Ok(archive)
+    }
+
+    /// Compares the entries of a WAD between two revisions by CRC and size.
+    ///
+    /// `None` unless the WAD exists and is indexed in both revisions.
+    pub async fn diff_wad(
+        &self,
+        from_revision: String,
+        to_revision: String,
+        wad_name: String,
+    ) -> Result<Option<WadDiff>, DbError> {
+        let diff = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<WadDiff>, DbError> {
+                let from = load_wad_index(conn, &from_revision, &wad_name)?;
+                let to = load_wad_index(conn, &to_revision, &wad_name)?;
+
+                Ok(from
+                    .zip(to)
+                    .map(|((_, from), (file_name, to))| WadDiff::new(file_name, &from, &to)))
+            })
+            .await?;
+
+        Ok(diff)
+    }
+
+    /// Looks up `entry_name` inside a WAD of `revision`.
+    ///
+    /// `wad_name` is either the WAD's path within the revision (e.g. `Data/GameData/Root.wad`) or just its file name,
+    /// a full path match wins. Only WADs that are mirrored and indexed are found.
+    pub async fn get_wad_entry(
+        &self,
+        revision_name: String,
+        wad_name: String,
+        entry_name: String,
+    ) -> Result<Option<WadEntryLocation>, DbError> {
+        let result = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<WadEntryLocation>, DbError> {
+                let location = conn
+                    .query_row(
+                        &format!("SELECT o.revision, o.file_name, e.name, e.data_offset, e.size, e.compressed_size, e.compressed, e.crc
+                        FROM assets a
+                        JOIN assets o ON o.revision = a.origin_revision AND o.file_name = a.file_name
+                        JOIN wad_entries e ON e.revision = o.revision AND e.file_name = o.file_name
+                        WHERE a.revision = ?1 AND o.mirrored = 1 AND e.name = ?3
+                            AND {WAD_NAME_MATCH}
+                        ORDER BY a.file_name = ?2 DESC, a.file_name LIMIT 1"),
+                        params![revision_name, wad_name, entry_name],
+                        |row| {
+                            Ok(WadEntryLocation {
+                                origin_revision: row.get(0)?,
+                                file_name: row.get(1)?,
+                                entry: WadEntry {
+                                    name: row.get(2)?,
+                                    offset: row.get(3)?,
+                                    size: row.get(4)?,
+                                    compressed_size: row.get(5)?,
+                                    compressed: row.get(6)?,
+                                    crc: row.get(7)?,
+                                },
+                            })
+                        },
+                    )
+                    .optional()?;
+
+                Ok(location)
+            })
+            .await?;
+
+        Ok(result)
+    }
+
+    /// `.lang` entries of indexed WADs whose strings weren't extracted yet, leaving out the ones that couldn't be.
+    pub async fn unextracted_string_tables(&self) -> miette::Result<Vec<WadEntryLocation>> {
+        let tables = self
+            .client
+            .conn_and_then(|conn| -> Result<Vec<WadEntryLocation>, DbError> {
+                let mut stmt = conn.prepare(
+                    "SELECT e.revision, e.file_name, e.name, e.data_offset, e.size, e.compressed_size, e.compressed, e.crc
+                    FROM wad_entries e
+                    JOIN assets o ON o.revision = e.revision AND o.file_name = e.file_name
+                    LEFT JOIN string_tables t ON t.revision = e.revision AND t.file_name = e.file_name
+                        AND t.entry_name = e.name
+                    WHERE o.mirrored = 1 AND e.name LIKE '%.lang' AND e.string_error IS NULL AND t.entry_name IS NULL
+                    ORDER BY e.revision, e.file_name, e.name",
+                )?;
+                let tables = stmt
+                    .query_map([], |row| {
+                        Ok(WadEntryLocation {
+                            origin_revision: row.get(0)?,
+                            file_name: row.get(1)?,
+                            entry: WadEntry {
+                                name: row.get(2)?,
+                                offset: row.get(3)?,
+                                size: row.get(4)?,
+                                compressed_size: row.get(5)?,
+                                compressed: row.get(6)?,
+                                crc: row.get(7)?,
+                            },
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(tables)
+            })
+            .await?;
+
+        Ok(tables)
+    }
+
+    /// Remembers that the strings of the entry at `location` can't be extracted, so it isn't tried again until its WAD
+    /// is downloaded again.
+    pub async fn mark_string_table_unreadable(
+        &self,
+        location: WadEntryLocation,
+        error: String,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_and_then(move |conn| -> Result<(), DbError> {
+                conn.execute(
+                    "UPDATE wad_entries SET string_error = ?4 WHERE revision = ?1 AND file_name = ?2 AND name = ?3",
+                    params![
+                        location.origin_revision,
+                        location.file_name,
+                        location.entry.name,
+                        error
+                    ],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stores the strings of a string table, replacing what was stored for it before.
+    pub async fn save_string_table(
+        &self,
+        location: WadEntryLocation,
+        locale: String,
+        table_name: String,
+        strings: Vec<(String, String)>,
+    ) -> miette::Result<()> {
+        self.client
+            .conn_mut_and_then(move |conn: &mut Connection| -> Result<(), DbError> {
+                let tx = conn.transaction().map_err(DbError::Transaction)?;
+                let WadEntryLocation {
+                    origin_revision,
+                    file_name,
+                    entry,
+                } = &location;
+
+                tx.execute(
+                    "DELETE FROM string_tables WHERE revision = ?1 AND file_name = ?2 AND entry_name = ?3",
+                    params![origin_revision, file_name, entry.name],
+                )?;
+                tx.execute(
+                    "INSERT INTO string_tables (revision, file_name, entry_name, locale, table_name)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![origin_revision, file_name, entry.name, locale, table_name],
+                )?;
+                {
+                    // Tables occasionally list a key twice, the game uses the last one. An upsert rather than
+                    // `OR REPLACE`, whose deletes wouldn't reach the triggers maintaining `strings_index`
+                    let mut stmt_insert = tx.prepare(
+                        "INSERT INTO locale_strings (revision, file_name, entry_name, key, text)
+                        VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT (revision, file_name, entry_name, key) DO UPDATE SET text = excluded.text",
+                    )?;
+                    for (key, text) in &strings {
+                        stmt_insert.execute(params![
+                            origin_revision,
+                            file_name,
+                            entry.name,
+                            key,
+                            text
+                        ])?;
+                    }
+                }
+
+                tx.commit().map_err(DbError::Transaction)?;
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Every string stored under `key` in `revision`, across locales and tables unless `locale` is given.
+    ///
+    /// `None` if the revision isn't tracked.
+    pub async fn lookup_string(
+        &self,
+        revision_name: String,
+        key: String,
+        locale: Option<String>,
+    ) -> Result<Option<Vec<LocaleString>>, DbError> {
+        let strings = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<Vec<LocaleString>>, DbError> {
+                Ok(load_strings(
+                    conn,
+                    &revision_name,
+                    Some(&key),
+                    locale.as_deref(),
+                )?)
+            })
+            .await?;
+
+        Ok(strings)
+    }
+
+    /// Strings of `revision` whose key or text matches `pattern`, optionally only those of one locale.
+    ///
+    /// Like `search`, candidates come from the trigram index if the pattern has a literal to look up, otherwise every
+    /// string of the revision is checked. `None` if the revision isn't tracked.
+    pub async fn search_strings(
+        &self,
+        revision_name: String,
+        pattern: Pattern,
+        locale: Option<String>,
+        limit: usize,
+    ) -> Result<Option<StringSearchResults>, DbError> {
+        let results = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<StringSearchResults>, DbError> {
+                let candidates = match pattern.literal() {
+                    Some(literal) => {
+                        if revision_number(conn, &revision_name)?.is_none() {
+                            return Ok(None);
+                        }
+
+                        let mut stmt = conn.prepare(
+                            "SELECT t.locale, t.table_name, s.key, s.text, s.revision
+                            FROM strings_index i
+                            JOIN locale_strings s ON s.rowid = i.rowid
+                            JOIN string_tables t ON t.revision = s.revision AND t.file_name = s.file_name
+                                AND t.entry_name = s.entry_name
+                            JOIN assets a ON a.origin_revision = t.revision AND a.file_name = t.file_name
+                            WHERE strings_index MATCH ?1 AND a.revision = ?2 AND (?3 IS NULL OR t.locale = ?3)
+                            ORDER BY t.locale, t.table_name, s.key",
+                        )?;
+                        stmt.query_map(
+                            params![fts_phrase(&literal), revision_name, locale],
+                            locale_string,
+                        )?
+                        .collect::<Result<Vec<_>, _>>()?
+                    }
+                    None => match load_strings(conn, &revision_name, None, locale.as_deref())? {
+                        Some(strings) => strings,
+                        None => return Ok(None),
+                    },
+                };
+
+                let mut strings: Vec<LocaleString> = candidates
+                    .into_iter()
+                    .filter(|string| pattern.is_match(&string.key) || pattern.is_match(&string.text))
+                    .take(limit + 1)
+                    .collect();
+                let truncated = strings.len() > limit;
+                strings.truncate(limit);
+
+                Ok(Some(StringSearchResults { strings, truncated }))
+            })
+            .await?;
+
+        Ok(results)
+    }
+
+    /// Compares the strings of two revisions, optionally only those of one locale.
+    ///
+    /// `None` unless both revisions are tracked.
+    pub async fn diff_strings(
+        &self,
+        from_revision: String,
+        to_revision: String,
+        locale: Option<String>,
+    ) -> Result<Option<StringDiff>, DbError> {
+        let diff = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<StringDiff>, DbError> {
+                let from = load_strings(conn, &from_revision, None, locale.as_deref())?;
+                let to = load_strings(conn, &to_revision, None, locale.as_deref())?;
+
+                Ok(from.zip(to).map(|(from, to)| {
+                    StringDiff::new(from_revision.clone(), to_revision.clone(), &from, &to)
+                }))
+            })
+            .await?;
+
+        Ok(diff)
+    }
+
+    /// Finds assets and WAD entries by name, along with the revisions containing them.
+    ///
+    /// Candidates come from the trigram index if the pattern has a literal to look up, otherwise every known name
+    /// is checked. `None` if a revision named by the filter isn't tracked.
+    pub async fn search(
+        &self,
+        pattern: Pattern,
+        filter: SearchFilter,
+    ) -> Result<Option<SearchResults>, DbError> {
+        let results = self
+            .client
+            .conn_and_then(move |conn| -> Result<Option<SearchResults>, DbError> {
+                let mut bounds = [i64::MIN, i64::MAX];
+                for (bound, name) in bounds.iter_mut().zip([&filter.from, &filter.to]) {
+                    if let Some(name) = name {
+                        match revision_number(conn, name)? {
+                            Some(number) => *bound = number,
+                            None => return Ok(None),
+                        }
+                    }
+                }
+                if let Some(name) = &filter.revision
+                    && revision_number(conn, name)?.is_none()
+                {
+                    return Ok(None);
+                }
+                let [from, to] = bounds;
+
+                let literal = pattern.literal();
+                let mut stmt_names = match &literal {
+                    Some(_) => conn.prepare(
+                        "SELECT n.wad, n.name FROM search_index s
+                        JOIN search_names n ON n.id = s.rowid
+                        WHERE search_index MATCH ?1
+                        ORDER BY n.name, n.wad",
+                    )?,
+                    None => {
+                        conn.prepare("SELECT wad, name FROM search_names ORDER BY name, wad")?
+                    }
+                };
+                let mut stmt_assets = conn.prepare(
+                    "SELECT a.revision, a.file_type FROM assets a
+                    JOIN revisions r ON r.revision_name = a.revision
+                    WHERE a.file_name = ?1 AND r.number BETWEEN ?2 AND ?3
+                        AND (?4 IS NULL OR a.file_type = ?4) AND (?5 IS NULL OR a.revision = ?5)
+                    ORDER BY r.number",
+                )?;
+                // Entries belong to the revision storing their WAD, every revision sharing the WAD contains them
+                let mut stmt_entries = conn.prepare(
+                    "SELECT a.revision, a.file_type FROM wad_entries e
+                    JOIN assets a ON a.origin_revision = e.revision AND a.file_name = e.file_name
+                    JOIN revisions r ON r.revision_name = a.revision
+                    WHERE e.file_name = ?1 AND e.name = ?2 AND r.number BETWEEN ?3 AND ?4
+                        AND (?5 IS NULL OR a.file_type = ?5) AND (?6 IS NULL OR a.revision = ?6)
+                    ORDER BY r.number",
+                )?;
+
+                let read_name =
+                    |row: &rusqlite::Row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?));
+                let names = match &literal {
+                    Some(literal) => {
+                        stmt_names.query_map(params![fts_phrase(literal)], read_name)?
+                    }
+                    None => stmt_names.query_map([], read_name)?,
+                };
+
+                let mut hits = Vec::new();
+                let mut truncated = false;
+                for name in names {
+                    let (wad, name) = name?;
+                    if !pattern.is_match(&name) {
+                        continue;
+                    }
+
+                    let read_revision =
+                        |row: &rusqlite::Row| Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?));
+                    let revisions = if wad.is_empty() {
+                        stmt_assets
+                            .query_map(
+                                params![name, from, to, filter.file_type, filter.revision],
+                                read_revision,
+                            )?
+                            .collect::<Result<Vec<_>, _>>()?
+                    } else {
+                        stmt_entries
+                            .query_map(
+                                params![wad, name, from, to, filter.file_type, filter.revision],
+                                read_revision,
+                            )?
+                            .collect::<Result<Vec<_>, _>>()?
+                    };
+                    let Some(&(_, file_type)) = revisions.last() else {
+                        continue;
+                    };
+
+                    if hits.len() == filter.limit {
+                        truncated = true;
+                        break;
+                    }
+                    let (file_name, entry_name) = if wad.is_empty() {
+                        (name, None)
+                    } else {
+                        (wad, Some(name))
+                    };
+                    hits.push(SearchHit {
+                        file_name,
+                        entry_name,
+                        file_type,
+                        revisions: revisions
+                            .into_iter()
+                            .map(|(revision, _)| revision)
+                            .collect(),
+                    });
+                }
+
+                Ok(Some(SearchResults { hits, truncated }))
+            })
+            .await?;
+
+        Ok(results)
+    }
+
+    /// The `limit` files with the most distinct versions across all tracked revisions.
+    pub async fn most_changed_files(&self, limit: usize) -> Result<Vec<FileChurn>, DbError> {
+        let files = self
+            .client
+            .conn_and_then(move |conn| -> Result<Vec<FileChurn>, DbError> {
+                // Every version has exactly one row that is its own origin. The bare `revision_name` is taken from
+                // the row with the highest number
+                let mut stmt = conn.prepare(
+                    "SELECT a.file_name, COUNT(*) AS versions, r.revision_name, MAX(r.number)
+                    FROM assets a
+                    JOIN revisions r ON r.revision_name = a.revision
+                    WHERE a.origin_revision = a.revision
+                    GROUP BY a.file_name
+                    ORDER BY versions DESC, a.file_name
+                    LIMIT ?1",
+                )?;
+
+                let files = stmt
+                    .query_map(params![limit as i64], |row| {
+                        Ok(FileChurn {
+                            file_name: row.get(0)?,
+                            versions: row.get(1)?,
+                            last_changed: row.get(2)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(files)
+            })
+            .await?;
+
+        Ok(files)
+    }
+
+    /// Total and unique size of every revision, oldest first.
+    pub async fn revision_growth(&self) -> Result<GrowthReport, DbError> {
+        let revisions = self
+            .client
+            .conn_and_then(move |conn| -> Result<Vec<RevisionGrowth>, DbError> {
+                let mut stmt = conn.prepare(
+                    "SELECT r.revision_name, r.number, COUNT(a.file_name), COALESCE(SUM(a.size), 0),
+                        COALESCE(SUM(a.origin_revision = a.revision), 0),
+                        COALESCE(SUM(CASE WHEN a.origin_revision = a.revision AND a.mirrored THEN a.size ELSE 0 END), 0)
+                    FROM revisions r
+                    LEFT JOIN assets a ON a.revision = r.revision_name
+                    GROUP BY r.revision_name
+                    ORDER BY r.number",
+                )?;
+
+                let revisions = stmt
+                    .query_map([], |row| {
+                        Ok(RevisionGrowth {
+                            revision: row.get(0)?,
+                            number: row.get(1)?,
+                            assets: row.get::<_, i64>(2)? as u64,
+                            total_size: row.get::<_, i64>(3)? as u64,
+                            size_delta: None,
+                            unique_assets: row.get::<_, i64>(4)? as u64,
+                            unique_bytes: row.get::<_, i64>(5)? as u64,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(revisions)
+            })
+            .await?;
+
+        Ok(GrowthReport::new(revisions))
+    }
+
+    /// How many revisions share each blob, with the `limit` longest chains.
+    pub async fn dedup_chains(&self, limit: usize) -> Result<DedupReport, DbError> {
+        let chains = self
+            .client
+            .conn_and_then(move |conn| -> Result<Vec<DedupChain>, DbError> {
+                let mut stmt = conn.prepare(
+                    "SELECT file_name, origin_revision, COUNT(*) FROM assets
+                    GROUP BY origin_revision, file_name",
+                )?;
+
+                let chains = stmt
+                    .query_map([], |row| {
+                        Ok(DedupChain {
+                            file_name: row.get(0)?,
+                            origin_revision: row.get(1)?,
+                            length: row.get(2)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(chains)
+            })
+            .await?;
+
+        Ok(DedupReport::new(chains, limit))
+    }
 }
@@ -0,0 +1,469 @@
+use crate::errors::DdsError;
+use image::RgbaImage;
+
+const MAGIC: &[u8; 4] = b"DDS ";
+/// Magic and the fixed-size header, where the data starts unless there's a DX10 header
+const HEADER_SIZE: usize = 4 + 124;
+const DX10_HEADER_SIZE: usize = 20;
+/// Textures larger than this in either dimension aren't decoded
+const MAX_DIMENSION: u32 = 16384;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x20000;
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    /// Uncompressed pixels of `bits` bits, channels given by their bit masks. A red mask without green and blue
+    /// masks is luminance.
+    Masked {
+        bits: u32,
+        masks: [u32; 4],
+    },
+}
+
+/// Decodes the top mip level of a DDS texture.
+///
+/// Supports BC1 (DXT1), BC2 (DXT3), BC3 (DXT5), BC4 and BC5 (ATI1/ATI2) and uncompressed RGB(A) and luminance
+/// formats, with or without a DX10 header. BC5 is treated as a normal map, blue is reconstructed from red and green.
+pub fn decode_dds(data: &[u8]) -> Result<RgbaImage, DdsError> {
+    if data.len() < HEADER_SIZE || !data.starts_with(MAGIC) {
+        return Err(DdsError::InvalidMagic);
+    }
+
+    let height = read_u32(data, 12);
+    let width = read_u32(data, 16);
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(DdsError::InvalidDimensions(width, height));
+    }
+
+    let flags = read_u32(data, 80);
+    let four_cc = &data[84..88];
+    let (format, offset) = if flags & DDPF_FOURCC != 0 && four_cc == b"DX10" {
+        if data.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(DdsError::Truncated);
+        }
+        let dxgi = read_u32(data, HEADER_SIZE);
+        (dxgi_format(dxgi)?, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else if flags & DDPF_FOURCC != 0 {
+        (four_cc_format(four_cc)?, HEADER_SIZE)
+    } else if flags & (DDPF_RGB | DDPF_LUMINANCE) != 0 {
+        let alpha = if flags & DDPF_ALPHAPIXELS != 0 {
+            read_u32(data, 104)
+        } else {
+            0
+        };
+        let format = Format::Masked {
+            bits: read_u32(data, 88),
+            masks: [
+                read_u32(data, 92),
+                read_u32(data, 96),
+                read_u32(data, 100),
+                alpha,
+            ],
+        };
+        (format, HEADER_SIZE)
+    } else {
+        return Err(DdsError::UnsupportedFormat(format!(
+            "pixel format flags {flags:#x}"
+        )));
+    };
+
+    // Checked before allocating the image, which takes up to 1 GiB for the largest dimensions
+    let data = &data[offset..];
+    if data.len() < format.data_length(width, height)? {
+        return Err(DdsError::Truncated);
+    }
+
+    let mut image = RgbaImage::new(width, height);
+    match format {
+        Format::Masked { bits, masks } => decode_masked(data, &mut image, bits, masks),
+        block => decode_blocks(data, &mut image, block),
+    }
+
+    Ok(image)
+}
+
+impl Format {
+    /// Bytes the top mip level of a `width` x `height` texture takes.
+    fn data_length(self, width: u32, height: u32) -> Result<usize, DdsError> {
+        let (width, height) = (width as usize, height as usize);
+
+        Ok(match self {
+            Self::Bc1 | Self::Bc4 => width.div_ceil(4) * height.div_ceil(4) * 8,
+            Self::Bc2 | Self::Bc3 | Self::Bc5 => width.div_ceil(4) * height.div_ceil(4) * 16,
+            Self::Masked { bits, .. } => {
+                if !matches!(bits, 8 | 16 | 24 | 32) {
+                    return Err(DdsError::UnsupportedFormat(format!(
+                        "{bits} bits per pixel"
+                    )));
+                }
+                width * height * (bits / 8) as usize
+            }
+        })
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(
+        data[offset..offset + 4]
+            .try_into()
+            .expect("slice has 4 bytes"),
+    )
+}
+
+fn four_cc_format(four_cc: &[u8]) -> Result<Format, DdsError> {
+    Ok(match four_cc {
+        b"DXT1" => Format::Bc1,
+        b"DXT2" | b"DXT3" => Format::Bc2,
+        b"DXT4" | b"DXT5" => Format::Bc3,
+        b"ATI1" | b"BC4U" => Format::Bc4,
+        b"ATI2" | b"BC5U" => Format::Bc5,
+        _ => {
+            return Err(DdsError::UnsupportedFormat(format!(
+                "FourCC {}",
+                String::from_utf8_lossy(four_cc)
+            )));
+        }
+    })
+}
+
+fn dxgi_format(dxgi: u32) -> Result<Format, DdsError> {
+    const RGBA: [u32; 4] = [0xFF, 0xFF00, 0xFF_0000, 0xFF00_0000];
+    const BGRA: [u32; 4] = [0xFF_0000, 0xFF00, 0xFF, 0xFF00_0000];
+    const BGRX: [u32; 4] = [0xFF_0000, 0xFF00, 0xFF, 0];
+
+    Ok(match dxgi {
+        70..=72 => Format::Bc1,
+        73..=75 => Format::Bc2,
+        76..=78 => Format::Bc3,
+        79 | 80 => Format::Bc4,
+        82 | 83 => Format::Bc5,
+        27..=29 => Format::Masked {
+            bits: 32,
+            masks: RGBA,
+        },
+        87 | 90 | 91 => Format::Masked {
+            bits: 32,
+            masks: BGRA,
+        },
+        88 | 92 | 93 => Format::Masked {
+            bits: 32,
+            masks: BGRX,
+        },
+        61 => Format::Masked {
+            bits: 8,
+            masks: [0xFF, 0, 0, 0],
+        },
+        _ => return Err(DdsError::UnsupportedFormat(format!("DXGI format {dxgi}"))),
+    })
+}
+
+fn decode_masked(data: &[u8], image: &mut RgbaImage, bits: u32, masks: [u32; 4]) {
+    let bytes = (bits / 8) as usize;
+    let luminance = masks[1] == 0 && masks[2] == 0;
+    for (pixel, source) in image.pixels_mut().zip(data.chunks_exact(bytes)) {
+        let mut value = [0u8; 4];
+        value[..bytes].copy_from_slice(source);
+        let value = u32::from_le_bytes(value);
+
+        let [r, g, b, a] = masks.map(|mask| channel(value, mask));
+        pixel.0 = if luminance {
+            [r, r, r, if masks[3] == 0 { 255 } else { a }]
+        } else {
+            [r, g, b, if masks[3] == 0 { 255 } else { a }]
+        };
+    }
+}
+
+/// Extracts the channel selected by `mask` and scales it to 8 bits.
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift = mask.trailing_zeros();
+    let max = u64::from(mask >> shift);
+    let channel = u64::from((value & mask) >> shift);
+    (channel * 255 / max) as u8
+}
+
+fn decode_blocks(data: &[u8], image: &mut RgbaImage, format: Format) {
+    let block_size = match format {
+        Format::Bc1 | Format::Bc4 => 8,
+        _ => 16,
+    };
+    let (width, height) = image.dimensions();
+    let blocks_x = width.div_ceil(4);
+    let blocks_y = height.div_ceil(4);
+
+    for (index, block) in data
+        .chunks_exact(block_size)
+        .take((blocks_x * blocks_y) as usize)
+        .enumerate()
+    {
+        let pixels = match format {
+            Format::Bc1 => color_block(block, true),
+            Format::Bc2 => {
+                let mut pixels = color_block(&block[8..], false);
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let nibble = (block[i / 2] >> ((i % 2) * 4)) & 0xF;
+                    pixel[3] = nibble * 17;
+                }
+                pixels
+            }
+            Format::Bc3 => {
+                let mut pixels = color_block(&block[8..], false);
+                for (pixel, alpha) in pixels.iter_mut().zip(alpha_block(block)) {
+                    pixel[3] = alpha;
+                }
+                pixels
+            }
+            Format::Bc4 => alpha_block(block).map(|red| [red, red, red, 255]),
+            Format::Bc5 => {
+                let red = alpha_block(block);
+                let green = alpha_block(&block[8..]);
+                std::array::from_fn(|i| {
+                    let x = f32::from(red[i]) / 127.5 - 1.0;
+                    let y = f32::from(green[i]) / 127.5 - 1.0;
+                    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+                    [red[i], green[i], ((z + 1.0) * 127.5) as u8, 255]
+                })
+            }
+            Format::Masked { .. } => unreachable!("masked formats aren't block-compressed"),
+        };
+
+        let block_x = index as u32 % blocks_x * 4;
+        let block_y = index as u32 / blocks_x * 4;
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            let x = block_x + i as u32 % 4;
+            let y = block_y + i as u32 / 4;
+            // Blocks at the right and bottom edge may reach past the image
+            if x < width && y < height {
+                image.put_pixel(x, y, image::Rgba(pixel));
+            }
+        }
+    }
+}
+
+/// Decodes the 8-byte color part of a BC1-BC3 block into 16 pixels, row by row.
+///
+/// Only BC1 blocks can use the three-color mode with transparent black.
+fn color_block(block: &[u8], bc1: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let [r0, g0, b0] = rgb565(c0);
+    let [r1, g1, b1] = rgb565(c1);
+    let mix = |a: u8, b: u8, wa: u16, wb: u16| {
+        ((u16::from(a) * wa + u16::from(b) * wb) / (wa + wb)) as u8
+    };
+
+    let colors = if c0 > c1 || !bc1 {
+        [
+            [r0, g0, b0, 255],
+            [r1, g1, b1, 255],
+            [mix(r0, r1, 2, 1), mix(g0, g1, 2, 1), mix(b0, b1, 2, 1), 255],
+            [mix(r0, r1, 1, 2), mix(g0, g1, 1, 2), mix(b0, b1, 1, 2), 255],
+        ]
+    } else {
+        [
+            [r0, g0, b0, 255],
+            [r1, g1, b1, 255],
+            [mix(r0, r1, 1, 1), mix(g0, g1, 1, 1), mix(b0, b1, 1, 1), 255],
+            [0, 0, 0, 0],
+        ]
+    };
+
+    std::array::from_fn(|i| colors[((indices >> (i * 2)) & 0b11) as usize])
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let r = (color >> 11) & 0x1F;
+    let g = (color >> 5) & 0x3F;
+    let b = color & 0x1F;
+
+    [
+        ((r * 255 + 15) / 31) as u8,
+        ((g * 255 + 31) / 63) as u8,
+        ((b * 255 + 15) / 31) as u8,
+    ]
+}
+
+/// Decodes an 8-byte BC3 alpha (or BC4/BC5 channel) block into 16 values, row by row.
+fn alpha_block(block: &[u8]) -> [u8; 16] {
+    let a0 = u16::from(block[0]);
+    let a1 = u16::from(block[1]);
+    let mut indices = [0u8; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+
+    let mut values = [a0 as u8, a1 as u8, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            values[i + 1] = (((7 - i as u16) * a0 + i as u16 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            values[i + 1] = (((5 - i as u16) * a0 + i as u16 * a1) / 5) as u8;
+        }
+    }
+
+    std::array::from_fn(|i| values[((indices >> (i * 3)) & 0b111) as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(
+        width: u32,
+        height: u32,
+        flags: u32,
+        four_cc: &[u8; 4],
+        bits: u32,
+        masks: [u32; 4],
+    ) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[..4].copy_from_slice(MAGIC);
+        data[4..8].copy_from_slice(&124u32.to_le_bytes());
+        data[12..16].copy_from_slice(&height.to_le_bytes());
+        data[16..20].copy_from_slice(&width.to_le_bytes());
+        data[80..84].copy_from_slice(&flags.to_le_bytes());
+        data[84..88].copy_from_slice(four_cc);
+        data[88..92].copy_from_slice(&bits.to_le_bytes());
+        for (i, mask) in masks.iter().enumerate() {
+            data[92 + i * 4..96 + i * 4].copy_from_slice(&mask.to_le_bytes());
+        }
+        data
+    }
+
+    fn compressed(width: u32, height: u32, four_cc: &[u8; 4], blocks: &[u8]) -> Vec<u8> {
+        let mut data = header(width, height, DDPF_FOURCC, four_cc, 0, [0; 4]);
+        data.extend(blocks);
+        data
+    }
+
+    #[test]
+    fn decodes_bc1_blocks() {
+        // Red and blue endpoints, the first four pixels use each of the four colors
+        let mut block = vec![0x00, 0xF8, 0x1F, 0x00];
+        block.extend(0xE4u32.to_le_bytes());
+        let image = decode_dds(&compressed(4, 4, b"DXT1", &block)).unwrap();
+
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(2, 0).0, [170, 0, 85, 255]);
+        assert_eq!(image.get_pixel(3, 0).0, [85, 0, 170, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_bc1_transparent_blocks() {
+        // The first endpoint isn't larger, so the block has three colors and transparent black
+        let mut block = vec![0x1F, 0x00, 0x00, 0xF8];
+        block.extend(0xE4u32.to_le_bytes());
+        let image = decode_dds(&compressed(4, 4, b"DXT1", &block)).unwrap();
+
+        assert_eq!(image.get_pixel(2, 0).0, [127, 0, 127, 255]);
+        assert_eq!(image.get_pixel(3, 0).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_bc3_blocks() {
+        // Alpha from 255 to 0, the first pixels use indices 0, 1, 2 and 7
+        let mut block = vec![255, 0, 0x88, 0x0E, 0, 0, 0, 0];
+        // Green and black, only the last pixel is black
+        block.extend([0xE0, 0x07, 0x00, 0x00]);
+        block.extend((1u32 << 30).to_le_bytes());
+        let image = decode_dds(&compressed(4, 4, b"DXT5", &block)).unwrap();
+
+        assert_eq!(image.get_pixel(0, 0).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 255, 0, 0]);
+        assert_eq!(image.get_pixel(2, 0).0, [0, 255, 0, 218]);
+        assert_eq!(image.get_pixel(3, 0).0, [0, 255, 0, 36]);
+        assert_eq!(image.get_pixel(3, 3).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn crops_edge_blocks() {
+        let mut blocks = Vec::new();
+        for color in [0xF800u16, 0x07E0] {
+            blocks.extend(color.to_le_bytes());
+            blocks.extend([0, 0, 0, 0, 0, 0]);
+        }
+        let image = decode_dds(&compressed(5, 3, b"DXT1", &blocks)).unwrap();
+
+        assert_eq!(image.dimensions(), (5, 3));
+        assert_eq!(image.get_pixel(3, 2).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(4, 2).0, [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_masked_pixels() {
+        let masks = [0xFF_0000, 0xFF00, 0xFF, 0xFF00_0000];
+        let mut data = header(2, 1, DDPF_RGB | DDPF_ALPHAPIXELS, &[0; 4], 32, masks);
+        data.extend([0x30, 0x20, 0x10, 0x80, 0xFF, 0x00, 0x00, 0xFF]);
+        let image = decode_dds(&data).unwrap();
+
+        assert_eq!(image.get_pixel(0, 0).0, [0x10, 0x20, 0x30, 0x80]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(matches!(decode_dds(b""), Err(DdsError::InvalidMagic)));
+        assert!(matches!(decode_dds(b"DDS "), Err(DdsError::InvalidMagic)));
+        assert!(matches!(
+            decode_dds(&[0xAB; 256]),
+            Err(DdsError::InvalidMagic)
+        ));
+
+        let zero = compressed(0, 4, b"DXT1", &[0; 8]);
+        assert!(matches!(
+            decode_dds(&zero),
+            Err(DdsError::InvalidDimensions(0, 4))
+        ));
+
+        let unknown = compressed(4, 4, b"ABCD", &[0; 8]);
+        assert!(matches!(
+            decode_dds(&unknown),
+            Err(DdsError::UnsupportedFormat(_))
+        ));
+
+        let bits = header(4, 4, DDPF_RGB, &[0; 4], 12, [0xF00, 0xF0, 0xF, 0]);
+        assert!(matches!(
+            decode_dds(&bits),
+            Err(DdsError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        // Four blocks needed, only one there
+        let data = compressed(8, 8, b"DXT1", &[0; 8]);
+        assert!(matches!(decode_dds(&data), Err(DdsError::Truncated)));
+
+        let data = compressed(4, 4, b"DX10", &[0; 8]);
+        assert!(matches!(decode_dds(&data), Err(DdsError::Truncated)));
+
+        // Rejected before the 1 GiB image is allocated
+        let masks = [0xFF, 0xFF00, 0xFF_0000, 0xFF00_0000];
+        let data = header(
+            MAX_DIMENSION,
+            MAX_DIMENSION,
+            DDPF_RGB | DDPF_ALPHAPIXELS,
+            &[0; 4],
+            32,
+            masks,
+        );
+        assert!(matches!(decode_dds(&data), Err(DdsError::Truncated)));
+    }
+}
@@ -1,4 +1,5 @@
 use miette::Diagnostic;
+use std::path::PathBuf;
 use thiserror::Error;
 
 // asset_fetcher.rs
@@ -12,6 +13,15 @@ pub enum AssetFetcherError {
         )
     )]
     ClientBuild(#[source] reqwest::Error),
+
+    #[error("Invalid glob pattern: {1}")]
+    #[diagnostic(
+        code(asset_fetcher::invalid_glob),
+        help(
+            "Check the glob patterns of [fetcher.download_order] and [fetcher.filter] in your config.toml."
+        )
+    )]
+    InvalidGlob(#[source] globset::Error, String),
 }
 
 // xml_parser.rs
@@ -129,9 +139,9 @@ pub enum WizardPatcherError {
 // manifest_fetcher.rs
 #[derive(Debug, Error, Diagnostic)]
 pub enum ManifestFetcherError {
-    #[error("File system I/O error")]
-    #[diagnostic(code(asset_fetcher::io))]
-    Io(#[source] std::io::Error),
+    #[error("Failed to access the manifest in storage")]
+    #[diagnostic(code(asset_fetcher::storage))]
+    Storage(#[source] StorageError),
 
     #[error("Failed to create HTTP client")]
     #[diagnostic(
@@ -159,13 +169,307 @@ pub enum FetcherTraitError {
     #[diagnostic(code(asset_fetcher::manifest_fetch))]
     Fetch(#[source] reqwest::Error, String),
 
-    #[error("File system I/O error")]
-    #[diagnostic(code(asset_fetcher::io))]
+    #[error("Failed to store {1}")]
+    #[diagnostic(code(asset_fetcher::store))]
+    Store(#[source] StorageError, String),
+}
+
+// preflight.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum PreflightError {
+    #[error(
+        "Not enough disk space for revision {revision}: {needed} bytes needed, {available} bytes available"
+    )]
+    #[diagnostic(
+        code(preflight::insufficient_space),
+        help(
+            "Free up space on the volume of save_directory, or move it to a larger one. The download is retried on the next check."
+        )
+    )]
+    InsufficientSpace {
+        revision: String,
+        needed: u64,
+        available: u64,
+    },
+
+    #[error(
+        "Storage quota exceeded by revision {revision}: {used} bytes used + {needed} bytes needed > {quota} bytes"
+    )]
+    #[diagnostic(
+        code(preflight::quota_exceeded),
+        help(
+            "Raise fetcher.quota in your config.toml, or free up space in the archive. The download is retried on the next check."
+        )
+    )]
+    QuotaExceeded {
+        revision: String,
+        needed: u64,
+        used: u64,
+        quota: u64,
+    },
+
+    #[error("Failed to determine free disk space")]
+    #[diagnostic(code(preflight::available_space))]
+    AvailableSpace(#[source] StorageError),
+
+    #[error("Failed to determine archive size")]
+    #[diagnostic(code(preflight::database))]
+    Database(#[source] DbError),
+}
+
+// replicator.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum ReplicationError {
+    #[error("Failed to create HTTP client")]
+    #[diagnostic(
+        code(replicator::client_build),
+        help(
+            "There was an error while creating the HTTP client. Please restart Aurorium or try again later."
+        )
+    )]
+    ClientBuild(#[source] reqwest::Error),
+
+    #[error("Failed to query the leader at {1}")]
+    #[diagnostic(
+        code(replicator::request),
+        help("Check that [replication] leader points at a running Aurorium instance.")
+    )]
+    Request(#[source] reqwest::Error, String),
+
+    #[error("Invalid response from the leader at {1}")]
+    #[diagnostic(
+        code(replicator::invalid_response),
+        help(
+            "The leader answered with something unexpected. Make sure both instances run the same Aurorium version."
+        )
+    )]
+    InvalidResponse(#[source] serde_json::Error, String),
+}
+
+// import_v3.rs, ingest.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum ImportError {
+    #[error("Failed to read {1}")]
+    #[diagnostic(code(import::read))]
+    Read(#[source] std::io::Error, PathBuf),
+
+    #[error("Unsupported archive {0}")]
+    #[diagnostic(
+        code(import::unsupported_archive),
+        help(
+            "Revisions can be ingested from a directory, or a .tar, .tar.gz/.tgz or .tar.zst archive."
+        )
+    )]
+    UnsupportedArchive(PathBuf),
+
+    #[error("Failed to extract {1}")]
+    #[diagnostic(code(import::extract))]
+    Extract(#[source] std::io::Error, PathBuf),
+
+    #[error("{problems} files of {revision} don't match its manifest")]
+    #[diagnostic(
+        code(import::validation),
+        help(
+            "Nothing was registered or moved. Every file the revision introduces has to be present with the size and CRC the manifest lists."
+        )
+    )]
+    Validation { revision: String, problems: usize },
+
+    #[error("No revisions found in {0}")]
+    #[diagnostic(
+        code(import::no_revisions),
+        help(
+            "Point the importer at the v3.x save directory, the one containing V_r*.Wizard_* directories with a LatestFileList.xml each."
+        )
+    )]
+    NoRevisions(PathBuf),
+}
+
+// bundle.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum BundleError {
+    #[error("Revision {0} isn't tracked")]
+    #[diagnostic(
+        code(bundle::unknown_revision),
+        help("GET /revisions lists the revisions that can be exported.")
+    )]
+    UnknownRevision(String),
+
+    #[error("Blob {0} isn't in storage")]
+    #[diagnostic(
+        code(bundle::missing_blob),
+        help(
+            "Run `aurorium reconcile` to see which files are missing, `--fix` downloads them again."
+        )
+    )]
+    MissingBlob(String),
+
+    #[error("Failed to write {1}")]
+    #[diagnostic(code(bundle::write))]
+    Write(#[source] std::io::Error, PathBuf),
+
+    #[error("Invalid bundle: {0}")]
+    #[diagnostic(
+        code(bundle::invalid),
+        help("Only bundles created by `aurorium export` can be imported.")
+    )]
+    Invalid(String),
+
+    #[error("{0} problems found in the bundle")]
+    #[diagnostic(
+        code(bundle::validation),
+        help(
+            "Nothing was imported. Revisions that deduplicate against revisions that are neither tracked nor in the bundle have to be imported together with them, and blobs shared with tracked revisions have to match the ones stored here."
+        )
+    )]
+    Validation(usize),
+}
+
+// wad.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum WadError {
+    #[error("Not a KIWAD archive")]
+    #[diagnostic(code(wad::invalid_magic))]
+    InvalidMagic,
+
+    #[error("Entry name of {0} bytes in the file table")]
+    #[diagnostic(
+        code(wad::name_too_long),
+        help(
+            "The archive is probably corrupted, `aurorium reconcile --fix` downloads damaged files again."
+        )
+    )]
+    NameTooLong(u32),
+
+    #[error("Failed to read KIWAD archive")]
+    #[diagnostic(code(wad::read))]
+    Read(#[from] std::io::Error),
+
+    #[error("Failed to download the WAD header")]
+    #[diagnostic(code(wad::header_download))]
+    HeaderDownload(#[source] reqwest::Error),
+
+    #[error("Got {actual} of {expected} header bytes")]
+    #[diagnostic(
+        code(wad::incomplete_header),
+        help(
+            "The source probably ignored the Range request, the WAD is indexed after its download instead."
+        )
+    )]
+    IncompleteHeader { expected: u32, actual: usize },
+
+    #[error("Header CRC is {actual:#010x}, but the manifest lists {expected:#010x}")]
+    #[diagnostic(
+        code(wad::header_crc),
+        help("The WAD is indexed after its download instead.")
+    )]
+    HeaderCrc { expected: u32, actual: u32 },
+
+    #[error("No source delivered the WAD header")]
+    #[diagnostic(
+        code(wad::header_unavailable),
+        help("The WAD is indexed after its download instead.")
+    )]
+    HeaderUnavailable,
+}
+
+// object_property/*.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum ObjectPropertyError {
+    #[error("Failed to read type list {1}")]
+    #[diagnostic(
+        code(object_property::read_type_list),
+        help("Check the `types` path of [object_property] in your config.toml.")
+    )]
+    ReadTypeList(#[source] std::io::Error, PathBuf),
+
+    #[error("Failed to parse type list")]
+    #[diagnostic(
+        code(object_property::invalid_type_list),
+        help("The type list has to be a JSON type dump in the wiztype/katsuba format.")
+    )]
+    InvalidTypeList(#[source] serde_json::Error),
+
+    #[error("Unsupported type list version {0}")]
+    #[diagnostic(
+        code(object_property::unsupported_type_list),
+        help("Only version 2 type dumps are supported, dump the types again with a current tool.")
+    )]
+    UnsupportedTypeList(u32),
+
+    #[error("Invalid type hash {0} in the type list")]
+    #[diagnostic(code(object_property::invalid_type_hash))]
+    InvalidTypeHash(String),
+
+    #[error("Unknown type hash {0}")]
+    #[diagnostic(
+        code(object_property::unknown_type),
+        help("The type list is probably from a different client version than the file.")
+    )]
+    UnknownType(u32),
+
+    #[error("Data ended unexpectedly")]
+    #[diagnostic(code(object_property::unexpected_end))]
+    UnexpectedEnd,
+
+    #[error("Corrupt data: {0}")]
+    #[diagnostic(code(object_property::corrupt))]
+    Corrupt(&'static str),
+
+    #[error("Failed to decompress object")]
+    #[diagnostic(code(object_property::decompress))]
+    Decompress(#[source] std::io::Error),
+}
+
+// dds.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum DdsError {
+    #[error("Not a DDS texture")]
+    #[diagnostic(code(dds::invalid_magic))]
+    InvalidMagic,
+
+    #[error("Invalid texture dimensions {0}x{1}")]
+    #[diagnostic(code(dds::invalid_dimensions))]
+    InvalidDimensions(u32, u32),
+
+    #[error("Unsupported texture format: {0}")]
+    #[diagnostic(
+        code(dds::unsupported_format),
+        help("Only BC1-BC5 and uncompressed RGB(A) and luminance textures can be converted.")
+    )]
+    UnsupportedFormat(String),
+
+    #[error("Texture data ended unexpectedly")]
+    #[diagnostic(code(dds::truncated))]
+    Truncated,
+}
+
+// search.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum SearchError {
+    #[error("Empty search query")]
+    #[diagnostic(code(search::empty_query), help("Pass what to look for as `?q=`."))]
+    EmptyQuery,
+
+    #[error("Invalid glob pattern: {1}")]
+    #[diagnostic(code(search::invalid_glob))]
+    InvalidGlob(#[source] globset::Error, String),
+
+    #[error("Invalid regular expression: {0}")]
+    #[diagnostic(code(search::invalid_regex))]
+    InvalidRegex(#[source] regex::Error),
+}
+
+// storage/*.rs
+#[derive(Debug, Error, Diagnostic)]
+pub enum StorageError {
+    #[error("Storage I/O error")]
+    #[diagnostic(code(storage::io))]
     Io(#[source] std::io::Error),
 
     #[error("Failed to create directories")]
     #[diagnostic(
-        code(asset_fetcher::create_dir),
+        code(storage::create_dir),
         help(
             "There was an error while creating directories. Please check your file system permissions and try again."
         )
@@ -173,8 +477,28 @@ pub enum FetcherTraitError {
     CreateDir(#[source] std::io::Error),
 
     #[error("failed to finalize downloaded file")]
-    #[diagnostic(code(asset_fetcher::rename))]
+    #[diagnostic(code(storage::rename))]
     Rename(#[source] std::io::Error),
+
+    #[error("Invalid storage key: {0}")]
+    #[diagnostic(
+        code(storage::invalid_key),
+        help("Storage keys must be relative paths that don't leave the archive.")
+    )]
+    InvalidKey(String),
+
+    #[error("Object storage error")]
+    #[diagnostic(code(storage::object_store))]
+    ObjectStore(#[source] object_store::Error),
+
+    #[error("Failed to configure the S3 storage backend")]
+    #[diagnostic(
+        code(storage::build),
+        help(
+            "Check the [storage] section of your config.toml (bucket, region, endpoint and credentials)."
+        )
+    )]
+    Build(#[source] object_store::Error),
 }
 
 // config.rs
@@ -209,6 +533,13 @@ pub enum ConfigError {
         help("Check your permissions and try again")
     )]
     PathError(#[source] std::io::Error),
+
+    #[error("Missing [{0}] section in config.toml")]
+    #[diagnostic(
+        code(config::missing_section),
+        help("This command needs the [{0}] section, check the documentation for reference!")
+    )]
+    MissingSection(&'static str),
 }
 
 #[derive(Debug, Error, Diagnostic)]
@@ -278,10 +609,57 @@ pub enum RouteError {
     #[error("Bad request: {0}")]
     BadRequest(String),
 
+    #[error("Asset is not mirrored: {0}")]
+    #[diagnostic(
+        code(route::not_mirrored),
+        help("The asset is known, but excluded by the mirror filters of this server.")
+    )]
+    NotMirrored(String),
+
+    #[error("Failed to fetch {1} from upstream")]
+    #[diagnostic(
+        code(route::upstream),
+        help("The asset isn't downloaded yet and the patch server couldn't deliver it either.")
+    )]
+    Upstream(#[source] reqwest::Error, String),
+
     #[error("Invalid working directory")]
     #[diagnostic(
         code(route::invalid_working_dir),
         help("The server's working directory is invalid. Please check the server configuration.")
     )]
     InvalidWorkingDir(#[from] std::io::Error),
+
+    #[error("Failed to read stored asset")]
+    #[diagnostic(
+        code(route::asset_read),
+        help("The asset exists but could not be read or decompressed. Check the file on disk.")
+    )]
+    AssetRead(#[source] std::io::Error),
+
+    #[error("Storage error: {0}")]
+    #[diagnostic(
+        code(route::storage_error),
+        help("Check that the storage backend is reachable and correctly configured.")
+    )]
+    Storage(#[from] StorageError),
+
+    #[error("{0}")]
+    #[diagnostic(
+        code(route::unsupported_format),
+        help("`?format=json` needs the [object_property] section in config.toml.")
+    )]
+    UnsupportedFormat(String),
+
+    #[error("Failed to decode object: {0}")]
+    #[diagnostic(code(route::object_property))]
+    ObjectProperty(#[from] ObjectPropertyError),
+
+    #[error("Failed to convert texture: {0}")]
+    #[diagnostic(code(route::texture))]
+    Texture(#[from] DdsError),
+
+    #[error("Invalid search: {0}")]
+    #[diagnostic(code(route::search))]
+    Search(#[from] SearchError),
 }
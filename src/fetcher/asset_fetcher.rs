@@ -1,16 +1,27 @@
 use crate::{
-    errors::AssetFetcherError, fetcher::fetcher::Fetcher, revision::Asset,
+    config::FetcherConfig,
+    errors::{AssetFetcherError, WadError},
+    fetcher::{
+        fetcher::Fetcher,
+        mirrors::{MirrorSet, Source},
+        ordering::order_assets,
+        packed::{extract_members, group_by_tar},
+        throttle::{BandwidthLimiter, ConcurrencyController, DownloadFailure},
+    },
+    revision::Asset,
+    storage::{DownloadClaim, LocalStorage, Storage, StorageBackend, asset_key, zstd_key},
+    utils::file_crc,
     wizard_patcher::WizardPatcher,
 };
 use futures_util::{StreamExt, stream};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use reqwest::Client;
+use reqwest::{Client, Response, StatusCode, header};
 use std::{
-    num::NonZeroUsize,
     path::{Path, PathBuf},
-    sync::LazyLock,
-    time::Duration,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
 };
+use tokio::time::timeout;
 use tracing::{debug, info, instrument, trace, warn};
 
 static MAIN_PROGRESS_STYLE: LazyLock<ProgressStyle> = LazyLock::new(|| {
@@ -25,43 +36,89 @@ static FILE_PROGRESS_STYLE: LazyLock<ProgressStyle> = LazyLock::new(|| {
         .expect("Failed to create progress bar style")
 });
 
+/// Name a tar is downloaded as inside its staging directory
+const TAR_STAGING_NAME: &str = "package.tar";
+
+/// What every fetch run of the process shares, so the bandwidth limit covers all of them together and mirror health
+/// carries over from one revision to the next.
+#[derive(Clone)]
+pub struct SharedDownloads {
+    /// Also throttles assets proxied from the patch server
+    pub limiter: Option<BandwidthLimiter>,
+    pub mirrors: Arc<MirrorSet>,
+}
+
+impl SharedDownloads {
+    pub fn new(config: &FetcherConfig) -> Self {
+        Self {
+            limiter: config.bandwidth_limit.map(BandwidthLimiter::new),
+            mirrors: Arc::new(MirrorSet::new(config.mirrors.as_ref())),
+        }
+    }
+}
+
 pub struct AssetFetcher<'a> {
     client: Client,
-    concurrent_downloads: &'a NonZeroUsize,
+    config: &'a FetcherConfig,
     wizard_patcher: WizardPatcher,
-    save_directory: PathBuf,
+    storage: Storage,
     assets: Vec<Asset>,
+    /// Shared by every download of the process, WAD headers and proxied assets included
+    limiter: Option<BandwidthLimiter>,
+    mirrors: Arc<MirrorSet>,
 }
 
 impl<'a> AssetFetcher<'a> {
-    pub fn new<P>(
+    pub fn new(
         wizard_patcher: WizardPatcher,
-        concurrent_downloads: &'a NonZeroUsize,
-        save_directory: P,
-        assets: Vec<Asset>,
-    ) -> miette::Result<Self>
-    where
-        P: AsRef<Path>,
-    {
+        config: &'a FetcherConfig,
+        storage: Storage,
+        mut assets: Vec<Asset>,
+        shared: &SharedDownloads,
+    ) -> miette::Result<Self> {
+        if let Some(order) = &config.download_order {
+            order_assets(&mut assets, order)?;
+        }
+
         let client = Client::builder()
             .user_agent("KingsIsle Patcher")
-            .pool_max_idle_per_host(concurrent_downloads.get())
+            .pool_max_idle_per_host(config.concurrent_downloads.get())
             .tcp_keepalive(Duration::from_mins(1))
             .timeout(Duration::from_mins(2))
             .build()
             .map_err(AssetFetcherError::ClientBuild)?;
-
         Ok(AssetFetcher {
             client,
-            save_directory: save_directory.as_ref().join(&wizard_patcher.revision.name),
             wizard_patcher,
-            concurrent_downloads,
+            storage,
+            config,
             assets,
+            limiter: shared.limiter.clone(),
+            mirrors: shared.mirrors.clone(),
         })
     }
 
+    /// The assets to download, in download order.
+    pub fn assets(&self) -> &[Asset] {
+        &self.assets
+    }
+
+    pub fn revision_name(&self) -> &str {
+        &self.wizard_patcher.revision.name
+    }
+
+    /// The patch server, then the mirrors.
+    fn sources(&self) -> Vec<Source> {
+        self.mirrors.sources(&self.wizard_patcher.url_prefix)
+    }
+
+    fn url(&self, source: &Source, file_name: &str) -> String {
+        self.mirrors
+            .url(source, &self.wizard_patcher.revision.name, file_name)
+    }
+
     #[instrument(skip(self))]
-    pub async fn fetch_assets(&self) -> miette::Result<()> {
+    pub async fn fetch_assets(&self) -> miette::Result<FetchReport> {
         if self.assets.is_empty() {
             return Err(miette::miette!("No assets to fetch"));
         }
@@ -69,79 +126,512 @@ impl<'a> AssetFetcher<'a> {
         debug!(
             "Starting download of {} assets with {} concurrent downloads",
             self.assets.len(),
-            self.concurrent_downloads
+            self.config.concurrent_downloads
         );
 
+        let concurrency = match &self.config.adaptive_concurrency {
+            Some(adaptive) => ConcurrencyController::adaptive(
+                self.config.concurrent_downloads,
+                adaptive.max_downloads,
+            ),
+            None => ConcurrencyController::fixed(self.config.concurrent_downloads),
+        };
         let multi_progress = MultiProgress::new();
         let main_progress = multi_progress.add(ProgressBar::new(self.assets.len() as u64));
         main_progress.set_style(MAIN_PROGRESS_STYLE.clone());
         main_progress.enable_steady_tick(Duration::from_millis(200));
 
-        let downloads = self.assets.iter().map(|file| {
-            let client = self.client.clone();
-            let url_prefix = self.wizard_patcher.url_prefix.clone();
-            let save_dir = self.save_directory.clone();
+        let (loose, packed) = group_by_tar(&self.assets);
+
+        let downloads = loose.into_iter().map(|file| {
+            let storage = self.storage.clone();
+            let revision_name = &self.wizard_patcher.revision.name;
+            let compression = self.config.compression.as_ref();
+            let limiter = self.limiter.as_ref();
+            let concurrency = concurrency.clone();
+            let mirrors = &self.mirrors;
 
             let multi_progress = multi_progress.clone();
             let main_progress = main_progress.clone();
 
             async move {
-                let url = format!("{url_prefix}/{}", file.file_name);
-                let save_path = save_dir.join(&file.file_name);
+                let plain_key = asset_key(revision_name, &file.file_name);
+                let compressed_key = zstd_key(&plain_key);
 
-                trace!(url = %url, file = %file.file_name, "starting download");
+                // A proxied request may be caching the asset, wait for it to finish before checking storage
+                let _claim = DownloadClaim::claim(&plain_key).await;
 
-                if save_path.exists() {
+                // Either representation counts, compression may have been toggled since the file was stored
+                if storage.exists(&plain_key).await.unwrap_or(false)
+                    || storage.exists(&compressed_key).await.unwrap_or(false)
+                {
                     trace!(file = %file.file_name, "already downloaded, skipping");
                     main_progress.inc(1);
-                    return;
+                    return DownloadOutcome::Skipped;
                 }
 
-                // Download the file and write it to disk
+                let _permit = concurrency.acquire().await;
+
+                // Download the file and write it to storage, falling back to the mirrors one by one
+                let save_key = if compression.is_some() {
+                    &compressed_key
+                } else {
+                    &plain_key
+                };
                 let file_progress = multi_progress.add(ProgressBar::new_spinner());
-                match client.get(&url).send().await {
-                    Ok(res) => {
-                        if !res.status().is_success() {
-                            warn!(response=res.status().as_u16(), file = %file.file_name, "failed to download asset");
-                            main_progress.inc(1);
-                            return;
-                        }
+                let mut outcome = DownloadOutcome::Failed(file.file_name.clone());
+
+                for source in self.sources() {
+                    let url = self.url(&source, &file.file_name);
+                    trace!(url = %url, file = %file.file_name, "starting download");
 
-                        let short_filename = file.file_name.rsplit('/').next().unwrap_or(&file.file_name);
-                        file_progress.set_style(FILE_PROGRESS_STYLE.clone());
-                        file_progress.set_message(short_filename.to_string());
-                        file_progress.set_length(res.content_length().unwrap_or(file.size.into()));
-
-                        match Self::write_to_file_streamed(&save_path, res, Some(&file_progress)).await {
-                            Ok(()) => {
-                                file_progress.finish_with_message("Done");
-                            }
-                            Err(e) => {
-                                warn!(error = %e, file = %file.file_name, "failed to write file to disk");
-                            }
+                    match self
+                        .download(
+                            &url,
+                            file,
+                            save_key,
+                            &file_progress,
+                            limiter,
+                            mirrors.slow_timeout(),
+                        )
+                        .await
+                    {
+                        Ok(latency) => {
+                            mirrors.record_success(&source, latency);
+                            concurrency.record_success(file.size.into());
+                            file_progress.finish_with_message("Done");
+                            outcome = DownloadOutcome::Served(file.file_name.clone(), source.base);
+                            break;
+                        }
+                        Err(failure) => {
+                            mirrors.record_failure(&source);
+                            concurrency.record_failure(failure);
                         }
-                    },
-                    Err(e) => {
-                        // TODO: Handle retries (or log failures in a separate list)
-                        warn!(error = %e, file = %file.file_name, "failed to download file");
                     }
                 }
 
                 main_progress.inc(1);
                 multi_progress.remove(&file_progress);
+                outcome
+            }
+        });
+
+        let mut outcomes = stream::iter(downloads)
+            .buffer_unordered(concurrency.max())
+            .collect::<Vec<DownloadOutcome>>()
+            .await;
+
+        // Assets packed into a tar are downloaded once per tar and extracted from it
+        let packages = packed.into_iter().map(|(tar_name, members)| {
+            let storage = self.storage.clone();
+            let revision_name = &self.wizard_patcher.revision.name;
+            let compression = self.config.compression.as_ref();
+            let limiter = self.limiter.as_ref();
+            let concurrency = concurrency.clone();
+            let mirrors = &self.mirrors;
+
+            let multi_progress = multi_progress.clone();
+            let main_progress = main_progress.clone();
+
+            async move {
+                let mut outcomes = Vec::with_capacity(members.len());
+                let mut missing = Vec::new();
+                for file in members {
+                    let plain_key = asset_key(revision_name, &file.file_name);
+                    if storage.exists(&plain_key).await.unwrap_or(false)
+                        || storage.exists(&zstd_key(&plain_key)).await.unwrap_or(false)
+                    {
+                        trace!(file = %file.file_name, "already downloaded, skipping");
+                        main_progress.inc(1);
+                        outcomes.push(DownloadOutcome::Skipped);
+                    } else {
+                        missing.push(file);
+                    }
+                }
+                if missing.is_empty() {
+                    return outcomes;
+                }
+
+                let _permit = concurrency.acquire().await;
+
+                let staging = match tempfile::tempdir() {
+                    Ok(staging) => staging,
+                    Err(e) => {
+                        warn!(error = %e, tar = %tar_name, "failed to create a staging directory");
+                        main_progress.inc(missing.len() as u64);
+                        outcomes.extend(
+                            missing
+                                .iter()
+                                .map(|file| DownloadOutcome::Failed(file.file_name.clone())),
+                        );
+                        return outcomes;
+                    }
+                };
+                let names: Vec<String> = missing.iter().map(|file| file.file_name.clone()).collect();
+                let file_progress = multi_progress.add(ProgressBar::new_spinner());
+                let mut extracted = None;
+
+                for source in self.sources() {
+                    let url = self.url(&source, tar_name);
+                    trace!(url = %url, tar = %tar_name, "starting tar download");
+
+                    let (archive, latency) = match self
+                        .download_tar(
+                            &url,
+                            tar_name,
+                            staging.path(),
+                            &file_progress,
+                            limiter,
+                            mirrors.slow_timeout(),
+                        )
+                        .await
+                    {
+                        Ok(downloaded) => downloaded,
+                        Err(failure) => {
+                            mirrors.record_failure(&source);
+                            concurrency.record_failure(failure);
+                            continue;
+                        }
+                    };
+
+                    let target = staging.path().to_path_buf();
+                    let members = names.clone();
+                    let tar_path = archive.clone();
+                    let result = tokio::task::spawn_blocking(move || {
+                        extract_members(&tar_path, &target, &members)
+                    })
+                    .await
+                    .map_err(std::io::Error::other)
+                    .flatten();
+
+                    match result {
+                        Ok(members) => {
+                            mirrors.record_success(&source, latency);
+                            let size = tokio::fs::metadata(&archive).await.map_or(0, |m| m.len());
+                            concurrency.record_success(size);
+                            file_progress.finish_with_message("Done");
+                            extracted = Some((archive, members, source.base));
+                            break;
+                        }
+                        Err(e) => {
+                            warn!(error = %e, url = %url, tar = %tar_name, "failed to extract tar");
+                            mirrors.record_failure(&source);
+                            concurrency.record_failure(DownloadFailure::Error);
+                        }
+                    }
+                }
+                multi_progress.remove(&file_progress);
+                main_progress.inc(missing.len() as u64);
+
+                let Some((archive, mut members, source)) = extracted else {
+                    outcomes.extend(
+                        missing
+                            .iter()
+                            .map(|file| DownloadOutcome::Failed(file.file_name.clone())),
+                    );
+                    return outcomes;
+                };
+
+                for file in missing {
+                    let Some(path) = members.remove(&file.file_name) else {
+                        warn!(file = %file.file_name, tar = %tar_name, "asset is missing from its tar");
+                        outcomes.push(DownloadOutcome::Failed(file.file_name.clone()));
+                        continue;
+                    };
+
+                    let size = tokio::fs::metadata(&path).await.map_or(0, |m| m.len());
+                    if size != u64::from(file.size) {
+                        warn!(file = %file.file_name, tar = %tar_name, size, expected = file.size, "asset in tar has the wrong size");
+                        outcomes.push(DownloadOutcome::Failed(file.file_name.clone()));
+                        continue;
+                    }
+
+                    let crc_path = path.clone();
+                    let crc = tokio::task::spawn_blocking(move || file_crc(&crc_path))
+                        .await
+                        .map_err(std::io::Error::other)
+                        .flatten();
+                    match crc {
+                        Ok(crc) if crc == file.crc => {}
+                        Ok(crc) => {
+                            warn!(file = %file.file_name, tar = %tar_name, crc, expected = file.crc, "asset in tar has the wrong CRC");
+                            outcomes.push(DownloadOutcome::Failed(file.file_name.clone()));
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!(error = %e, file = %file.file_name, "failed to read asset extracted from tar");
+                            outcomes.push(DownloadOutcome::Failed(file.file_name.clone()));
+                            continue;
+                        }
+                    }
+
+                    let key = asset_key(revision_name, &file.file_name);
+                    match storage.move_file(&key, &path, compression).await {
+                        Ok(_) => outcomes.push(DownloadOutcome::Served(file.file_name.clone(), source.clone())),
+                        Err(e) => {
+                            warn!(error = %e, file = %file.file_name, "failed to store file");
+                            outcomes.push(DownloadOutcome::Failed(file.file_name.clone()));
+                        }
+                    }
+                }
+
+                // The tar itself stays available to clients that ask for it
+                let tar_key = asset_key(revision_name, tar_name);
+                if let Err(e) = storage.move_file(&tar_key, &archive, None).await {
+                    warn!(error = %e, tar = %tar_name, "failed to store tar");
+                }
+
+                outcomes
             }
         });
 
-        stream::iter(downloads)
-            .buffer_unordered(self.concurrent_downloads.get())
-            .collect::<Vec<()>>()
+        let packed_outcomes = stream::iter(packages)
+            .buffer_unordered(concurrency.max())
+            .collect::<Vec<Vec<DownloadOutcome>>>()
             .await;
+        outcomes.extend(packed_outcomes.into_iter().flatten());
 
         multi_progress.clear().unwrap();
         info!("All downloads completed");
 
-        Ok(())
+        let mut report = FetchReport::default();
+        for outcome in outcomes {
+            match outcome {
+                DownloadOutcome::Skipped => {}
+                DownloadOutcome::Served(file_name, source) => {
+                    report.served_by.push((file_name, source));
+                }
+                DownloadOutcome::Failed(file_name) => report.failed.push(file_name),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Requests `url`, or only its first `length` bytes, classifying why it failed if it did.
+    ///
+    /// Also returns how long the response headers took, the latency mirror health is based on.
+    async fn request(
+        &self,
+        url: &str,
+        file_name: &str,
+        length: Option<u32>,
+        slow_timeout: Option<Duration>,
+    ) -> Result<(Response, Duration), DownloadFailure> {
+        let started = Instant::now();
+        let mut request = self.client.get(url);
+        if let Some(length) = length {
+            request = request.header(header::RANGE, format!("bytes=0-{}", length - 1));
+        }
+        let request = request.send();
+        let response = match slow_timeout {
+            Some(slow_timeout) => timeout(slow_timeout, request).await.map_err(|_| {
+                warn!(url = %url, file = %file_name, "source is too slow, trying the next one");
+                DownloadFailure::Timeout
+            })?,
+            None => request.await,
+        };
+
+        let res = response.map_err(|e| {
+            // TODO: Handle retries (or log failures in a separate list)
+            warn!(error = %e, url = %url, file = %file_name, "failed to download file");
+            if e.is_timeout() {
+                DownloadFailure::Timeout
+            } else {
+                DownloadFailure::Error
+            }
+        })?;
+
+        if !res.status().is_success() {
+            warn!(response = res.status().as_u16(), url = %url, file = %file_name, "failed to download asset");
+            return Err(match res.status() {
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                    DownloadFailure::Throttled
+                }
+                _ => DownloadFailure::Error,
+            });
+        }
+
+        Ok((res, started.elapsed()))
     }
+
+    /// Downloads a single asset from `url` into `save_key`, returning the latency of the source.
+    ///
+    /// Fails without storing anything if the body doesn't have the size and CRC the manifest lists.
+    async fn download(
+        &self,
+        url: &str,
+        file: &Asset,
+        save_key: &str,
+        file_progress: &ProgressBar,
+        limiter: Option<&BandwidthLimiter>,
+        slow_timeout: Option<Duration>,
+    ) -> Result<Duration, DownloadFailure> {
+        let (res, latency) = self
+            .request(url, &file.file_name, None, slow_timeout)
+            .await?;
+
+        let short_filename = file.file_name.rsplit('/').next().unwrap_or(&file.file_name);
+        file_progress.set_style(FILE_PROGRESS_STYLE.clone());
+        file_progress.set_message(short_filename.to_string());
+        file_progress.set_length(res.content_length().unwrap_or(file.size.into()));
+        file_progress.set_position(0);
+
+        let compression = self.config.compression.as_ref();
+        Self::store_response(
+            &self.storage,
+            save_key,
+            res,
+            Some(file_progress),
+            compression,
+            limiter,
+            Some((file.size.into(), file.crc)),
+        )
+        .await
+        .map_err(|e| {
+            warn!(error = %e, url = %url, file = %file.file_name, "failed to store file");
+            DownloadFailure::Error
+        })?;
+
+        Ok(latency)
+    }
+
+    /// Downloads the tar `tar_name` from `url` into `staging`, returning where it was written and the latency of the
+    /// source.
+    async fn download_tar(
+        &self,
+        url: &str,
+        tar_name: &str,
+        staging: &Path,
+        file_progress: &ProgressBar,
+        limiter: Option<&BandwidthLimiter>,
+        slow_timeout: Option<Duration>,
+    ) -> Result<(PathBuf, Duration), DownloadFailure> {
+        let (res, latency) = self.request(url, tar_name, None, slow_timeout).await?;
+
+        let short_filename = tar_name.rsplit('/').next().unwrap_or(tar_name);
+        file_progress.set_style(FILE_PROGRESS_STYLE.clone());
+        file_progress.set_message(short_filename.to_string());
+        file_progress.set_length(res.content_length().unwrap_or_default());
+        file_progress.set_position(0);
+
+        // Tars are only kept as they were downloaded, never compressed
+        let staging_storage = Storage::Local(LocalStorage::new(staging));
+        Self::store_response(
+            &staging_storage,
+            TAR_STAGING_NAME,
+            res,
+            Some(file_progress),
+            None,
+            limiter,
+            None,
+        )
+        .await
+        .map_err(|e| {
+            warn!(error = %e, file = %tar_name, "failed to store tar");
+            DownloadFailure::Error
+        })?;
+
+        Ok((staging.join(TAR_STAGING_NAME), latency))
+    }
+
+    /// Downloads the header of `asset`, the first `header_size` bytes, from the first source whose copy matches
+    /// `header_crc`.
+    pub async fn download_header(&self, asset: &Asset) -> Result<Vec<u8>, WadError> {
+        let mut error = WadError::HeaderUnavailable;
+
+        for source in self.sources() {
+            let url = self.url(&source, &asset.file_name);
+            trace!(url = %url, file = %asset.file_name, "requesting header");
+
+            let (result, latency) = match self
+                .request(
+                    &url,
+                    &asset.file_name,
+                    Some(asset.header_size),
+                    self.mirrors.slow_timeout(),
+                )
+                .await
+            {
+                Ok((response, latency)) => (
+                    read_header(response, asset, self.limiter.as_ref()).await,
+                    latency,
+                ),
+                Err(_) => {
+                    self.mirrors.record_failure(&source);
+                    continue;
+                }
+            };
+
+            match result {
+                Ok(bytes) => {
+                    self.mirrors.record_success(&source, latency);
+                    return Ok(bytes);
+                }
+                Err(e) => {
+                    debug!(error = %e, url = %url, "source delivered an unusable header");
+                    self.mirrors.record_failure(&source);
+                    error = e;
+                }
+            }
+        }
+
+        Err(error)
+    }
+}
+
+/// Reads the first `header_size` bytes of `response` and verifies them against `header_crc`.
+async fn read_header(
+    mut response: Response,
+    asset: &Asset,
+    limiter: Option<&BandwidthLimiter>,
+) -> Result<Vec<u8>, WadError> {
+    let expected = asset.header_size as usize;
+
+    // Servers ignoring the Range header send the whole file, which is cut off after the header
+    let mut bytes = Vec::with_capacity(expected);
+    while bytes.len() < expected
+        && let Some(chunk) = response.chunk().await.map_err(WadError::HeaderDownload)?
+    {
+        if let Some(limiter) = limiter {
+            limiter.consume(chunk.len()).await;
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    bytes.truncate(expected);
+    if bytes.len() < expected {
+        return Err(WadError::IncompleteHeader {
+            expected: asset.header_size,
+            actual: bytes.len(),
+        });
+    }
+
+    let actual = crc32fast::hash(&bytes);
+    if actual != asset.header_crc {
+        return Err(WadError::HeaderCrc {
+            expected: asset.header_crc,
+            actual,
+        });
+    }
+
+    Ok(bytes)
+}
+
+enum DownloadOutcome {
+    /// Already in storage
+    Skipped,
+    /// File name and base URL of the source that served it
+    Served(String, String),
+    Failed(String),
+}
+
+/// Outcome of a fetch run.
+#[derive(Debug, Default)]
+pub struct FetchReport {
+    /// File name and base URL of the source that served it, for every asset downloaded in this run
+    pub served_by: Vec<(String, String)>,
+    /// Assets that couldn't be downloaded from any source
+    pub failed: Vec<String>,
 }
 
 impl Fetcher for AssetFetcher<'_> {}
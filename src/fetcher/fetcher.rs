@@ -1,11 +1,17 @@
-use crate::errors::FetcherTraitError;
+use crate::{
+    config::CompressionConfig,
+    errors::FetcherTraitError,
+    fetcher::throttle::BandwidthLimiter,
+    storage::{Storage, StorageBackend},
+    utils::verified,
+};
+use async_compression::{Level, tokio::bufread::ZstdEncoder};
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
 use indicatif::ProgressBar;
 use reqwest::{Client, Response};
-use std::path::{Path, PathBuf};
-use tokio::{
-    fs::create_dir_all,
-    io::{AsyncWriteExt, BufWriter},
-};
+use std::{io, pin::Pin};
+use tokio_util::io::StreamReader;
 
 pub trait Fetcher {
     async fn fetch(client: &Client, url: &str) -> miette::Result<Response> {
@@ -16,79 +22,58 @@ pub trait Fetcher {
             .map_err(|e| FetcherTraitError::Fetch(e, url.to_string()))?)
     }
 
-    /// Streams an HTTP response to disk, optionally driving a progress bar.
+    /// Streams an HTTP response into the storage backend, optionally driving a progress bar.
     ///
-    /// # Panics
-    /// This function will panic if any of the following conditions are met:
-    /// - The file path is invalid.
-    /// - The response body cannot be read.
-    /// - The file cannot be created or written to.
-    /// - The file cannot be renamed to its final name after writing.
+    /// Every chunk is accounted against `limiter` before it is passed on, which throttles the download itself.
+    /// If `compression` is set, the body is zstd-compressed on the way, so `key` should already carry the `.zst` suffix.
+    /// With the `expected` size and CRC, a body that doesn't match them fails the write.
+    /// The backend takes care of only exposing the object under `key` once it was written completely.
     ///
     /// # TODO
     /// Implement resuming downloads by checking for a .part file and continuing from where it left off. (Their server supports range requests, so this should be possible.)
-    async fn write_to_file_streamed<P>(
-        path: P,
-        mut response: reqwest::Response,
+    async fn store_response(
+        storage: &Storage,
+        key: &str,
+        response: Response,
         progress: Option<&ProgressBar>,
-    ) -> miette::Result<()>
-    where
-        P: AsRef<Path>,
-    {
-        let final_path = path.as_ref();
-        let part_path = Self::part_path(final_path);
-
-        // Check if parent dir exists, else create it
-        if let Some(parent) = final_path.parent() {
-            create_dir_all(parent)
-                .await
-                .map_err(FetcherTraitError::CreateDir)?;
-        }
+        compression: Option<&CompressionConfig>,
+        limiter: Option<&BandwidthLimiter>,
+        expected: Option<(u64, u32)>,
+    ) -> miette::Result<()> {
+        let progress = progress.cloned();
+        let limiter = limiter.cloned();
 
-        let file = tokio::fs::File::create(&part_path)
-            .await
-            .map_err(FetcherTraitError::Io)?;
-        let mut writer = BufWriter::with_capacity(128 * 1024, file); // TODO: Let the user configure this buffer size(?)
-
-        // Stream response to file in chunks (to avoid loading the entire file into memory)
-        let result: miette::Result<()> = async {
-            while let Some(chunk) = response
-                .chunk()
-                .await
-                .map_err(|e| miette::miette!("Failed to read chunk: {e}"))?
-            {
-                writer
-                    .write_all(&chunk)
-                    .await
-                    .map_err(FetcherTraitError::Io)?;
+        // Stream response to storage in chunks (to avoid loading the entire file into memory)
+        let body = response.bytes_stream().then(move |chunk| {
+            let progress = progress.clone();
+            let limiter = limiter.clone();
 
-                if let Some(pb) = progress {
+            async move {
+                let chunk = chunk.map_err(std::io::Error::other)?;
+                if let Some(limiter) = &limiter {
+                    limiter.consume(chunk.len()).await;
+                }
+                if let Some(pb) = &progress {
                     pb.inc(chunk.len() as u64);
                 }
+                Ok::<_, std::io::Error>(chunk)
             }
+        });
+        let body: Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>> = match expected {
+            Some((size, crc)) => Box::pin(verified(Box::pin(body), size, crc)),
+            None => Box::pin(body),
+        };
+        let reader = StreamReader::new(body);
 
-            writer.flush().await.map_err(FetcherTraitError::Io)?;
-            Ok(())
-        }
-        .await;
-
-        // If there was an error during the download, remove the partial file and return the error
-        if let Err(e) = result {
-            let _ = tokio::fs::remove_file(&part_path).await;
-            return Err(e);
-        }
-
-        // Rename the .part file to the final filename
-        tokio::fs::rename(&part_path, final_path)
-            .await
-            .map_err(FetcherTraitError::Rename)?;
+        let result = match compression {
+            Some(config) => {
+                let encoder = ZstdEncoder::with_quality(reader, Level::Precise(config.level));
+                storage.put_stream(key, encoder).await
+            }
+            None => storage.put_stream(key, reader).await,
+        };
 
+        result.map_err(|e| FetcherTraitError::Store(e, key.to_string()))?;
         Ok(())
     }
-
-    fn part_path(path: &Path) -> PathBuf {
-        let mut part_os = path.as_os_str().to_owned();
-        part_os.push(".part");
-        PathBuf::from(part_os)
-    }
 }
@@ -0,0 +1,58 @@
+use crate::{
+    config::MirrorFilterConfig, errors::AssetFetcherError, revision::Asset, utils::compile_glob_set,
+};
+use globset::GlobSet;
+
+/// Decides which assets are mirrored, based on `[fetcher.filter]`.
+pub struct MirrorFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    file_types: Option<Vec<u32>>,
+    exclude_file_types: Vec<u32>,
+}
+
+impl MirrorFilter {
+    pub fn new(config: &MirrorFilterConfig) -> Result<Self, AssetFetcherError> {
+        let compile = |patterns: &Option<Vec<String>>| {
+            patterns
+                .as_ref()
+                .map(|patterns| {
+                    compile_glob_set(patterns)
+                        .map_err(|e| AssetFetcherError::InvalidGlob(e, patterns.join(", ")))
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            include: compile(&config.include)?,
+            exclude: compile(&config.exclude)?,
+            file_types: config.file_types.clone(),
+            exclude_file_types: config.exclude_file_types.clone().unwrap_or_default(),
+        })
+    }
+
+    pub fn is_mirrored(&self, asset: &Asset) -> bool {
+        let included = self
+            .include
+            .as_ref()
+            .is_none_or(|include| include.is_match(&asset.file_name));
+        let excluded = self
+            .exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude.is_match(&asset.file_name));
+        let type_included = self
+            .file_types
+            .as_ref()
+            .is_none_or(|file_types| file_types.contains(&asset.file_type));
+        let type_excluded = self.exclude_file_types.contains(&asset.file_type);
+
+        included && !excluded && type_included && !type_excluded
+    }
+
+    /// Splits `assets` into the ones to mirror and the ones that are left out.
+    pub fn split(&self, assets: Vec<Asset>) -> (Vec<Asset>, Vec<Asset>) {
+        assets
+            .into_iter()
+            .partition(|asset| self.is_mirrored(asset))
+    }
+}
@@ -1,27 +1,24 @@
 use crate::{
-    errors::ManifestFetcherError, fetcher::fetcher::Fetcher, revision::Asset,
-    wizard_patcher::WizardPatcher, xml_parser::parse_file_list,
+    errors::{FetcherTraitError, ManifestFetcherError},
+    fetcher::fetcher::Fetcher,
+    revision::Asset,
+    storage::{Storage, StorageBackend, asset_key},
+    wizard_patcher::WizardPatcher,
+    xml_parser::parse_file_list_from_reader,
 };
 use reqwest::Client;
-use std::{
-    path::{Path, PathBuf},
-    time::Duration,
-};
-use tokio::fs::try_exists;
+use std::{io::Cursor, time::Duration};
 use tracing::{debug, info};
 
 /// This struct is responsible for fetching the `LatestFileList.bin` and `LatestFileList.xml` from their servers.
 pub struct ManifestFetcher {
     client: Client,
     wizard_patcher: WizardPatcher,
-    save_directory: PathBuf,
+    storage: Storage,
 }
 
 impl ManifestFetcher {
-    pub fn new<P>(wizard_patcher: WizardPatcher, save_directory: P) -> miette::Result<Self>
-    where
-        P: AsRef<Path>,
-    {
+    pub fn new(wizard_patcher: WizardPatcher, storage: Storage) -> miette::Result<Self> {
         let client = Client::builder()
             .user_agent("KingsIsle Patcher")
             .tcp_keepalive(Duration::from_mins(1))
@@ -31,40 +28,85 @@ impl ManifestFetcher {
 
         Ok(Self {
             client,
-            wizard_patcher: wizard_patcher.clone(),
-            save_directory: save_directory.as_ref().join(wizard_patcher.revision.name),
+            wizard_patcher,
+            storage,
         })
     }
 
+    fn key(&self, file_name: &str) -> String {
+        asset_key(&self.wizard_patcher.revision.name, file_name)
+    }
+
     pub async fn fetch_bin_manifest(&self) -> miette::Result<()> {
-        let path = self.save_directory.join("LatestFileList.bin");
-        let file_exists = try_exists(&path).await.map_err(ManifestFetcherError::Io)?;
+        let key = self.key("LatestFileList.bin");
+        let file_exists = self
+            .storage
+            .exists(&key)
+            .await
+            .map_err(ManifestFetcherError::Storage)?;
 
         if !file_exists {
             info!("Fetching LatestFileList.bin...");
             let response = Self::fetch(&self.client, &self.wizard_patcher.list_file_url).await?;
-            Self::write_to_file_streamed(&path, response, None).await?;
+            Self::store_response(&self.storage, &key, response, None, None, None, None).await?;
             return Ok(());
         }
 
-        info!(path = %path.display(), "BIN manifest already cached, skipping download");
+        info!(key = %key, "BIN manifest already cached, skipping download");
         Ok(())
     }
 
     pub async fn fetch_xml_manifest(&self) -> miette::Result<Vec<Asset>> {
-        let path = self.save_directory.join("LatestFileList.xml");
-        let file_exists = try_exists(&path).await.map_err(ManifestFetcherError::Io)?;
+        let key = self.key("LatestFileList.xml");
+        let file_exists = self
+            .storage
+            .exists(&key)
+            .await
+            .map_err(ManifestFetcherError::Storage)?;
         let list_file_url = self.wizard_patcher.list_file_url.replace(".bin", ".xml");
 
         if !file_exists {
             info!("Fetching LatestFileList.xml...");
             let response = Self::fetch(&self.client, &list_file_url).await?;
-            Self::write_to_file_streamed(&path, response, None).await?;
+            Self::store_response(&self.storage, &key, response, None, None, None, None).await?;
         }
 
-        info!(path = %path.display(), "XML manifest already cached, skipping download");
+        info!(key = %key, "XML manifest already cached, skipping download");
+
+        let content = self
+            .storage
+            .read_to_end(&key)
+            .await
+            .map_err(ManifestFetcherError::Storage)?;
+
+        Self::parse_xml_manifest(content)
+    }
+
+    /// Like `fetch_xml_manifest`, but never writes to storage. A cached manifest is still used if there is one.
+    pub async fn peek_xml_manifest(&self) -> miette::Result<Vec<Asset>> {
+        let key = self.key("LatestFileList.xml");
+        let content = match self.storage.exists(&key).await {
+            Ok(true) => self
+                .storage
+                .read_to_end(&key)
+                .await
+                .map_err(ManifestFetcherError::Storage)?,
+            _ => {
+                let list_file_url = self.wizard_patcher.list_file_url.replace(".bin", ".xml");
+                let response = Self::fetch(&self.client, &list_file_url).await?;
+                response
+                    .bytes()
+                    .await
+                    .map_err(|e| FetcherTraitError::Fetch(e, list_file_url))?
+                    .to_vec()
+            }
+        };
+
+        Self::parse_xml_manifest(content)
+    }
 
-        let assets = parse_file_list(path).unwrap_or(vec![]);
+    fn parse_xml_manifest(content: Vec<u8>) -> miette::Result<Vec<Asset>> {
+        let assets = parse_file_list_from_reader(Cursor::new(content)).unwrap_or(vec![]);
         debug!("Parsed {} entries from LatestFileList.xml", assets.len());
 
         if assets.is_empty() {
@@ -0,0 +1,126 @@
+use crate::config::{MirrorStrategy, MirrorsConfig};
+use std::{sync::Mutex, time::Duration};
+
+/// Where an asset can be downloaded from.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// Base URL, recorded in the database as the source of the asset
+    pub base: String,
+    /// Index into the configured mirrors, `None` for the patch server itself
+    mirror: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Health {
+    successes: u32,
+    failures: u32,
+    /// Exponentially weighted average of the time until the response headers arrived
+    latency: Option<Duration>,
+}
+
+impl Health {
+    /// Higher is better. Unknown mirrors start out neutral, so they get a chance to prove themselves.
+    fn score(&self) -> f64 {
+        let success_rate =
+            f64::from(self.successes + 1) / f64::from(self.successes + self.failures + 2);
+        let latency = self.latency.map_or(1.0, |latency| latency.as_secs_f64());
+
+        success_rate / (1.0 + latency)
+    }
+}
+
+/// The configured alternative sources to the patch server, and how well each of them has been doing.
+///
+/// The patch server is always tried first, mirrors only when it fails or doesn't answer within `slow_timeout`.
+/// Kept for the lifetime of the process, so their health carries over from one revision to the next.
+pub struct MirrorSet {
+    mirrors: Vec<String>,
+    strategy: MirrorStrategy,
+    slow_timeout: Option<Duration>,
+    health: Mutex<Vec<Health>>,
+}
+
+impl MirrorSet {
+    pub fn new(config: Option<&MirrorsConfig>) -> Self {
+        let mirrors: Vec<String> = config
+            .map(|config| {
+                config
+                    .urls
+                    .iter()
+                    .map(|url| url.trim_end_matches('/').to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            health: Mutex::new(vec![Health::default(); mirrors.len()]),
+            mirrors,
+            strategy: config
+                .and_then(|config| config.strategy)
+                .unwrap_or(MirrorStrategy::Ordered),
+            slow_timeout: config
+                .and_then(|config| config.slow_timeout)
+                .map(Duration::from_secs),
+        }
+    }
+
+    /// How long to wait for a source to start answering before moving on to the next one.
+    /// Only applies while there is a next one.
+    pub fn slow_timeout(&self) -> Option<Duration> {
+        self.slow_timeout.filter(|_| !self.mirrors.is_empty())
+    }
+
+    /// The sources to try for an asset, in order, starting with the patch server at `primary`.
+    pub fn sources(&self, primary: &str) -> Vec<Source> {
+        let mut order: Vec<usize> = (0..self.mirrors.len()).collect();
+
+        if let MirrorStrategy::Health = self.strategy {
+            let health = self.health.lock().expect("mirror health poisoned");
+            order.sort_by(|a, b| health[*b].score().total_cmp(&health[*a].score()));
+        }
+
+        let primary = Source {
+            base: primary.to_string(),
+            mirror: None,
+        };
+
+        std::iter::once(primary)
+            .chain(order.into_iter().map(|idx| Source {
+                base: self.mirrors[idx].clone(),
+                mirror: Some(idx),
+            }))
+            .collect()
+    }
+
+    /// Mirrors are other Aurorium instances (or share their layout), so they are addressed by `/{revision}/{file}`.
+    pub fn url(&self, source: &Source, revision: &str, file_name: &str) -> String {
+        match source.mirror {
+            Some(_) => format!("{}/{revision}/{file_name}", source.base),
+            None => format!("{}/{file_name}", source.base),
+        }
+    }
+
+    /// Records a download `source` served, `latency` being how long it took to send the response headers.
+    pub fn record_success(&self, source: &Source, latency: Duration) {
+        let Some(idx) = source.mirror else {
+            return;
+        };
+
+        let mut health = self.health.lock().expect("mirror health poisoned");
+        let entry = &mut health[idx];
+        entry.successes += 1;
+        entry.latency = Some(match entry.latency {
+            Some(average) => average.mul_f64(0.8) + latency.mul_f64(0.2),
+            None => latency,
+        });
+    }
+
+    /// Records a download `source` failed or delivered damaged data for.
+    pub fn record_failure(&self, source: &Source) {
+        let Some(idx) = source.mirror else {
+            return;
+        };
+
+        self.health.lock().expect("mirror health poisoned")[idx].failures += 1;
+    }
+}
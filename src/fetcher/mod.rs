@@ -1,3 +1,13 @@
 pub mod asset_fetcher;
+#[allow(clippy::module_inception)]
 pub mod fetcher;
+pub mod filter;
 pub mod manifest_fetcher;
+pub mod mirrors;
+pub mod ordering;
+pub mod packed;
+pub mod preflight;
+pub mod replicator;
+pub mod throttle;
+pub mod wad_indexer;
+pub mod wad_prefetch;
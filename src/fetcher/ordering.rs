@@ -0,0 +1,45 @@
+use crate::{
+    config::DownloadOrder, errors::AssetFetcherError, revision::Asset, utils::compile_glob,
+};
+use globset::GlobMatcher;
+
+/// Sorts `assets` according to `order`. The sort is stable, ties keep their manifest order.
+pub fn order_assets(assets: &mut [Asset], order: &DownloadOrder) -> Result<(), AssetFetcherError> {
+    match order {
+        DownloadOrder::Manifest => {}
+        DownloadOrder::SmallestFirst => assets.sort_by_key(|asset| asset.size),
+        DownloadOrder::CriticalFirst => assets.sort_by_key(|asset| !is_critical(asset)),
+        DownloadOrder::Priority { patterns } => {
+            let matchers = patterns
+                .iter()
+                .map(|pattern| {
+                    compile_glob(pattern)
+                        .map(|glob| glob.compile_matcher())
+                        .map_err(|e| AssetFetcherError::InvalidGlob(e, pattern.clone()))
+                })
+                .collect::<Result<Vec<GlobMatcher>, _>>()?;
+
+            assets.sort_by_cached_key(|asset| {
+                matchers
+                    .iter()
+                    .position(|matcher| matcher.is_match(&asset.file_name))
+                    .unwrap_or(matchers.len())
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Files the client can't start without: executables, anything in the game root and `Root.wad`.
+fn is_critical(asset: &Asset) -> bool {
+    let name = asset.file_name.as_str();
+    let base_name = name.rsplit('/').next().unwrap_or(name);
+    let extension = base_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+
+    !name.contains('/')
+        || base_name.eq_ignore_ascii_case("Root.wad")
+        || matches!(extension.as_deref(), Some("exe" | "dll"))
+}
@@ -0,0 +1,86 @@
+use crate::revision::Asset;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+/// Name of the tar upstream delivers `asset` in, `None` if it's downloaded on its own.
+pub fn tar_name(asset: &Asset) -> Option<&str> {
+    asset
+        .tar_file_name
+        .as_deref()
+        .filter(|tar| *tar != asset.file_name)
+}
+
+/// Splits assets into the ones downloaded on their own and the ones packed into tars, grouped by tar.
+pub fn group_by_tar(assets: &[Asset]) -> (Vec<&Asset>, BTreeMap<&str, Vec<&Asset>>) {
+    let mut loose = Vec::new();
+    let mut packed: BTreeMap<&str, Vec<&Asset>> = BTreeMap::new();
+
+    for asset in assets {
+        match tar_name(asset) {
+            Some(tar) => packed.entry(tar).or_default().push(asset),
+            None => loose.push(asset),
+        }
+    }
+
+    (loose, packed)
+}
+
+/// Extracts `members` from the tar at `archive` into `target`, returning the path each one was written to.
+///
+/// Entries are matched by their full path, or by their file name if the tar doesn't mirror the directory layout.
+/// Members that aren't in the tar are missing from the result.
+pub fn extract_members(
+    archive: &Path,
+    target: &Path,
+    members: &[String],
+) -> std::io::Result<HashMap<String, PathBuf>> {
+    let by_path: HashMap<&str, usize> = members
+        .iter()
+        .enumerate()
+        .map(|(index, member)| (member.as_str(), index))
+        .collect();
+    let by_file_name: HashMap<&str, usize> = members
+        .iter()
+        .enumerate()
+        .map(|(index, member)| (file_name(member), index))
+        .collect();
+
+    let mut exact = vec![false; members.len()];
+    let mut extracted = HashMap::new();
+
+    let mut tar = tar::Archive::new(BufReader::new(File::open(archive)?));
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?.to_string_lossy().replace('\\', "/");
+        let path = path.trim_start_matches("./");
+        let index = match by_path.get(path) {
+            Some(&index) => {
+                exact[index] = true;
+                index
+            }
+            // A file name match never replaces a full path match
+            None => match by_file_name.get(file_name(path)) {
+                Some(&index) if !exact[index] => index,
+                _ => continue,
+            },
+        };
+
+        let destination = target.join(index.to_string());
+        std::io::copy(&mut entry, &mut File::create(&destination)?)?;
+        extracted.insert(members[index].clone(), destination);
+    }
+
+    Ok(extracted)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
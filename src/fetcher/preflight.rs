@@ -0,0 +1,109 @@
+use crate::{
+    config::FetcherConfig,
+    db::Database,
+    errors::PreflightError,
+    revision::Asset,
+    storage::{Storage, StorageBackend, asset_key, zstd_key},
+};
+use miette::Diagnostic;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+
+/// A fetch that was refused by the preflight checks, as reported by `/status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FetchRefusal {
+    pub revision: String,
+    /// Diagnostic code, e.g. `preflight::quota_exceeded`
+    pub code: Option<String>,
+    pub message: String,
+    pub help: Option<String>,
+    /// RFC 3339 timestamp of the refusal
+    pub refused_at: String,
+}
+
+/// State of the revision checker that is shared with the file server.
+#[derive(Debug, Clone, Default)]
+pub struct FetchStatus {
+    refusal: Arc<RwLock<Option<FetchRefusal>>>,
+}
+
+impl FetchStatus {
+    pub fn refusal(&self) -> Option<FetchRefusal> {
+        self.refusal.read().expect("fetch status poisoned").clone()
+    }
+
+    pub fn set_refused(&self, revision: &str, error: &PreflightError) {
+        let refusal = FetchRefusal {
+            revision: revision.to_string(),
+            code: error.code().map(|code| code.to_string()),
+            message: error.to_string(),
+            help: error.help().map(|help| help.to_string()),
+            refused_at: chrono::Utc::now().to_rfc3339(),
+        };
+
+        *self.refusal.write().expect("fetch status poisoned") = Some(refusal);
+    }
+
+    pub fn clear(&self) {
+        *self.refusal.write().expect("fetch status poisoned") = None;
+    }
+}
+
+/// Checks that the queued assets of a revision fit on the storage volume and into `fetcher.quota`.
+///
+/// Sizes are taken from the manifest, so compression at rest only makes the estimate more conservative.
+pub async fn preflight(
+    config: &FetcherConfig,
+    storage: &Storage,
+    db: &Database,
+    revision: &str,
+    assets: &[Asset],
+) -> Result<(), PreflightError> {
+    let needed: u64 = assets.iter().map(|asset| u64::from(asset.size)).sum();
+
+    if let Some(available) = storage
+        .available_space()
+        .map_err(PreflightError::AvailableSpace)?
+    {
+        // Assets are queued again until the whole revision is downloaded, the ones already stored take no more space
+        let mut missing = 0;
+        for asset in assets {
+            let key = asset_key(revision, &asset.file_name);
+            if !storage.exists(&key).await.unwrap_or(false)
+                && !storage.exists(&zstd_key(&key)).await.unwrap_or(false)
+            {
+                missing += u64::from(asset.size);
+            }
+        }
+
+        debug!(missing, available, "checking free disk space");
+        if missing > available {
+            return Err(PreflightError::InsufficientSpace {
+                revision: revision.to_string(),
+                needed: missing,
+                available,
+            });
+        }
+    }
+
+    // `used` leaves out the whole revision, so its blobs count in full whether they're stored already or not
+    if let Some(quota) = config.quota {
+        let used = db
+            .stored_bytes(revision.to_string())
+            .await
+            .map_err(PreflightError::Database)?;
+
+        debug!(needed, used, quota, "checking storage quota");
+        if used + needed > quota.get() {
+            return Err(PreflightError::QuotaExceeded {
+                revision: revision.to_string(),
+                needed,
+                used,
+                quota: quota.get(),
+            });
+        }
+    }
+
+    Ok(())
+}
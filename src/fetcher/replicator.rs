@@ -0,0 +1,220 @@
+use crate::{
+    config::AppConfig,
+    db::{Database, RevisionMetadata},
+    errors::ReplicationError,
+    fetcher::{
+        asset_fetcher::{AssetFetcher, SharedDownloads},
+        fetcher::Fetcher,
+        preflight::{FetchStatus, preflight},
+    },
+    revision::{Asset, Revision},
+    storage::{Storage, StorageBackend, asset_key},
+    wizard_patcher::WizardPatcher,
+};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+const MANIFESTS: [&str; 2] = ["LatestFileList.bin", "LatestFileList.xml"];
+
+/// Mirrors another Aurorium instance (the leader) through its HTTP API, instead of talking to the patch server.
+///
+/// Revisions are copied with their asset rows as-is, so `origin_revision`, `mirrored` and `source` match the leader,
+/// and blobs are downloaded from the leader's `/{revision}/{file}` route.
+pub struct Replicator<'a> {
+    client: Client,
+    leader: String,
+    config: &'a AppConfig,
+    db: Database,
+    storage: Storage,
+    status: FetchStatus,
+    downloads: SharedDownloads,
+}
+
+impl<'a> Replicator<'a> {
+    pub fn new(
+        leader: &str,
+        config: &'a AppConfig,
+        db: Database,
+        storage: Storage,
+        status: FetchStatus,
+        downloads: SharedDownloads,
+    ) -> miette::Result<Self> {
+        let client = Client::builder()
+            .user_agent(concat!("Aurorium/", env!("CARGO_PKG_VERSION")))
+            .tcp_keepalive(Duration::from_mins(1))
+            .timeout(Duration::from_mins(2))
+            .build()
+            .map_err(ReplicationError::ClientBuild)?;
+
+        Ok(Self {
+            client,
+            leader: leader.trim_end_matches('/').to_string(),
+            config,
+            db,
+            storage,
+            status,
+            downloads,
+        })
+    }
+
+    /// Replicates every revision the leader has and we don't, oldest first, plus the leader's latest revision.
+    ///
+    /// The latest revision is always revisited, so downloads that failed or hadn't finished on the leader yet are picked up on the next poll.
+    pub async fn sync(&self) -> miette::Result<()> {
+        let latest = self.leader_latest().await?;
+        let Some(latest) = latest else {
+            info!("Leader doesn't have any revisions yet");
+            return Ok(());
+        };
+
+        let known = self.db.list_revisions().await?;
+        let mut pending: Vec<String> = if known.contains(&latest) {
+            vec![]
+        } else {
+            let revisions: Vec<String> = self.get_json("/revisions").await?;
+            // The leader lists newest first, but origin revisions have to exist before the revisions pointing at them
+            revisions
+                .into_iter()
+                .rev()
+                .filter(|revision| !known.contains(revision))
+                .collect()
+        };
+        if !pending.contains(&latest) {
+            pending.push(latest);
+        }
+
+        debug!(
+            "Replicating {} revisions from {}",
+            pending.len(),
+            self.leader
+        );
+        for revision in pending {
+            self.replicate(&revision).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn replicate(&self, revision_name: &str) -> miette::Result<()> {
+        let metadata: RevisionMetadata =
+            self.get_json(&format!("/metadata/{revision_name}")).await?;
+        let revision = Revision {
+            name: metadata.name.clone(),
+            number: metadata.number,
+        };
+
+        // Only the blobs this revision introduced live under its name, the rest are stored with their origin revision
+        let assets: Vec<Asset> = metadata
+            .assets
+            .iter()
+            .filter(|asset| asset.mirrored && asset.origin_revision == metadata.name)
+            .map(Asset::from)
+            .collect();
+
+        info!(
+            "Replicating revision {revision} ({} assets, {} stored under it)",
+            metadata.assets.len(),
+            assets.len()
+        );
+        self.db.insert_replicated_revision(metadata).await?;
+        self.fetch_manifests(&revision.name).await?;
+
+        if assets.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = preflight(
+            &self.config.fetcher,
+            &self.storage,
+            &self.db,
+            &revision.name,
+            &assets,
+        )
+        .await
+        {
+            self.status.set_refused(&revision.name, &e);
+            return Err(e.into());
+        }
+        self.status.clear();
+
+        let base = format!("{}/{}", self.leader, revision.name);
+        let wizard_patcher = WizardPatcher {
+            list_file_url: format!("{base}/{}", MANIFESTS[0]),
+            url_prefix: base,
+            revision,
+        };
+        let asset_fetcher = AssetFetcher::new(
+            wizard_patcher,
+            &self.config.fetcher,
+            self.storage.clone(),
+            assets,
+            &self.downloads,
+        )?;
+
+        let report = asset_fetcher.fetch_assets().await?;
+        if !report.failed.is_empty() {
+            warn!(
+                "{} assets couldn't be downloaded from the leader, retrying on the next poll",
+                report.failed.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Manifests are stored uncompressed, just like the leader stores them.
+    async fn fetch_manifests(&self, revision_name: &str) -> miette::Result<()> {
+        for manifest in MANIFESTS {
+            let key = asset_key(revision_name, manifest);
+            if self.storage.exists(&key).await.unwrap_or(false) {
+                continue;
+            }
+
+            let url = format!("{}/{revision_name}/{manifest}", self.leader);
+            let response = Self::fetch(&self.client, &url).await?;
+            if !response.status().is_success() {
+                warn!(response = response.status().as_u16(), url = %url, "leader doesn't have the manifest");
+                continue;
+            }
+
+            Self::store_response(&self.storage, &key, response, None, None, None, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The leader answers `/latest` with the bare revision name, or `{}` if it has none.
+    async fn leader_latest(&self) -> miette::Result<Option<String>> {
+        let body = self.get_text("/latest").await?;
+        let body = body.trim();
+
+        Ok((!body.is_empty() && !body.starts_with('{')).then(|| body.to_string()))
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> miette::Result<T> {
+        let body = self.get_text(path).await?;
+
+        Ok(serde_json::from_str(&body)
+            .map_err(|e| ReplicationError::InvalidResponse(e, format!("{}{path}", self.leader)))?)
+    }
+
+    async fn get_text(&self, path: &str) -> miette::Result<String> {
+        let url = format!("{}{path}", self.leader);
+        let body = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ReplicationError::Request(e, url.clone()))?
+            .text()
+            .await
+            .map_err(|e| ReplicationError::Request(e, url))?;
+
+        Ok(body)
+    }
+}
+
+impl Fetcher for Replicator<'_> {}
@@ -0,0 +1,218 @@
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::sleep;
+use tracing::debug;
+
+/// How long throughput is measured before the adaptive controller considers raising the limit.
+const MEASUREMENT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Throughput has to grow by at least this factor between two windows to count as an improvement.
+const IMPROVEMENT_THRESHOLD: f64 = 1.05;
+
+/// Token bucket shared by all downloads of a fetch run, so their combined throughput stays below the configured limit.
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+    bytes_per_second: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+struct Bucket {
+    /// Can become negative, which makes the following consumers wait until the debt is paid off
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl BandwidthLimiter {
+    pub fn new(bytes_per_second: NonZeroU64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.get() as f64,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: 0.0,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes `bytes` out of the bucket and sleeps until they are covered by the limit.
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("bandwidth bucket poisoned");
+            let now = Instant::now();
+            let refill =
+                now.duration_since(bucket.last_refill).as_secs_f64() * self.bytes_per_second;
+
+            // Allow bursts of at most one second worth of bytes
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_second);
+            bucket.last_refill = now;
+            bucket.tokens -= bytes as f64;
+
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / self.bytes_per_second)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Why a download didn't complete, as far as the concurrency controller is concerned.
+#[derive(Debug, Clone, Copy)]
+pub enum DownloadFailure {
+    /// The CDN answered with `429` or `503`
+    Throttled,
+    Timeout,
+    Error,
+}
+
+/// Limits how many downloads run at once.
+///
+/// In adaptive mode the limit grows by one while throughput keeps improving, and is halved whenever a download fails.
+pub struct ConcurrencyController {
+    semaphore: Arc<Semaphore>,
+    adaptive: bool,
+    max: usize,
+    state: Mutex<ControllerState>,
+}
+
+struct ControllerState {
+    limit: usize,
+    /// Permits that still have to be retired once running downloads hand them back
+    debt: usize,
+    window_start: Instant,
+    window_bytes: u64,
+    last_throughput: f64,
+}
+
+impl ConcurrencyController {
+    /// A controller that always allows `limit` concurrent downloads.
+    pub fn fixed(limit: NonZeroUsize) -> Arc<Self> {
+        Self::new(limit.get(), limit.get(), false)
+    }
+
+    /// A controller that starts at `initial` and adapts between one and `max` concurrent downloads.
+    pub fn adaptive(initial: NonZeroUsize, max: NonZeroUsize) -> Arc<Self> {
+        let max = max.get().max(initial.get());
+        Self::new(initial.get(), max, true)
+    }
+
+    fn new(limit: usize, max: usize, adaptive: bool) -> Arc<Self> {
+        Arc::new(Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            adaptive,
+            max,
+            state: Mutex::new(ControllerState {
+                limit,
+                debt: 0,
+                window_start: Instant::now(),
+                window_bytes: 0,
+                last_throughput: 0.0,
+            }),
+        })
+    }
+
+    /// Upper bound of concurrently running downloads, used to size the download stream.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub async fn acquire(self: &Arc<Self>) -> ConcurrencyPermit {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("download semaphore is never closed");
+
+        ConcurrencyPermit {
+            permit: Some(permit),
+            controller: self.clone(),
+        }
+    }
+
+    pub fn record_success(&self, bytes: u64) {
+        if !self.adaptive {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("concurrency state poisoned");
+        state.window_bytes += bytes;
+
+        let elapsed = state.window_start.elapsed();
+        if elapsed < MEASUREMENT_WINDOW {
+            return;
+        }
+
+        let throughput = state.window_bytes as f64 / elapsed.as_secs_f64();
+        if throughput > state.last_throughput * IMPROVEMENT_THRESHOLD && state.limit < self.max {
+            if state.debt > 0 {
+                state.debt -= 1;
+            } else {
+                self.semaphore.add_permits(1);
+            }
+            state.limit += 1;
+            debug!(
+                limit = state.limit,
+                throughput, "raising download concurrency"
+            );
+        }
+
+        state.last_throughput = throughput;
+        state.window_start = Instant::now();
+        state.window_bytes = 0;
+    }
+
+    pub fn record_failure(&self, failure: DownloadFailure) {
+        if !self.adaptive {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("concurrency state poisoned");
+        let new_limit = (state.limit / 2).max(1);
+        let reduction = state.limit - new_limit;
+        if reduction == 0 {
+            return;
+        }
+
+        // Idle permits can be retired right away, the rest once their downloads finish
+        let forgotten = self.semaphore.forget_permits(reduction);
+        state.debt += reduction - forgotten;
+        state.limit = new_limit;
+
+        // Start measuring from scratch, the old baseline no longer applies
+        state.window_start = Instant::now();
+        state.window_bytes = 0;
+        state.last_throughput = 0.0;
+
+        debug!(limit = new_limit, ?failure, "lowering download concurrency");
+    }
+
+    fn release(&self, permit: OwnedSemaphorePermit) {
+        let mut state = self.state.lock().expect("concurrency state poisoned");
+        if state.debt > 0 {
+            state.debt -= 1;
+            permit.forget();
+        }
+    }
+}
+
+/// A download slot. Handing it back may retire it, if the controller lowered the limit in the meantime.
+pub struct ConcurrencyPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    controller: Arc<ConcurrencyController>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            self.controller.release(permit);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    db::Database,
+    localization::{parse_string_table, string_table_name},
+    storage::{Storage, asset_key},
+    wad::{WadEntry, is_stored, open_entry, read_wad_header},
+};
+use tokio::io::{AsyncReadExt, BufReader};
+use tracing::{debug, info, warn};
+
+/// String tables larger than this aren't extracted, they'd have to be held in memory at once
+const MAX_STRING_TABLE_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Indexes the file table of every downloaded WAD that wasn't indexed yet, and returns how many were.
+/// Their string tables are extracted afterwards.
+///
+/// Only the origin row of a WAD is indexed, revisions sharing it find the entries through `origin_revision`.
+/// WADs that aren't in storage yet are picked up by a later run, ones that can't be parsed only once they're downloaded again.
+pub async fn index_wads(db: &Database, storage: &Storage) -> miette::Result<usize> {
+    let wads = db.unindexed_wads().await?;
+    let mut indexed = 0;
+
+    for (revision, file_name) in wads {
+        let key = asset_key(&revision, &file_name);
+        let Some(reader) = storage.open_blob(&key).await? else {
+            continue;
+        };
+
+        match read_wad_header(&mut BufReader::new(reader)).await {
+            Ok(archive) => {
+                debug!(key = %key, entries = archive.entries.len(), "indexed WAD");
+                db.save_wad_index(revision, file_name, archive).await?;
+                indexed += 1;
+            }
+            Err(e) => {
+                let error = e.to_string();
+                warn!(key = %key, "Failed to index WAD: {:?}", miette::Report::new(e));
+                db.mark_wad_unreadable(revision, file_name, error).await?;
+            }
+        }
+    }
+
+    if indexed > 0 {
+        info!("Indexed the file tables of {indexed} WADs");
+    }
+
+    extract_strings(db, storage).await?;
+
+    Ok(indexed)
+}
+
+/// Extracts the strings of every `.lang` entry of an indexed WAD that wasn't extracted yet, and returns how many
+/// tables were. Entries that can't be read are recorded and skipped until their WAD is downloaded again.
+async fn extract_strings(db: &Database, storage: &Storage) -> miette::Result<usize> {
+    let tables = db.unextracted_string_tables().await?;
+    let mut extracted = 0;
+
+    for location in tables {
+        let key = asset_key(&location.origin_revision, &location.file_name);
+        // Indexed from its header, but not downloaded yet
+        if !is_stored(storage, &key).await? {
+            continue;
+        }
+
+        let read = match string_table_name(&location.entry.name) {
+            None => Err("not named like a string table".to_string()),
+            Some(_) if location.entry.size > MAX_STRING_TABLE_SIZE => Err(format!(
+                "{} bytes, more than the {MAX_STRING_TABLE_SIZE} that are extracted",
+                location.entry.size
+            )),
+            Some(name) => read_entry(storage, &key, &location.entry)
+                .await
+                .map(|data| (name, data))
+                .map_err(|e| e.to_string()),
+        };
+        let ((locale, table), data) = match read {
+            Ok(read) => read,
+            Err(error) => {
+                warn!(key = %key, entry = %location.entry.name, error = %error, "Failed to read string table");
+                db.mark_string_table_unreadable(location, error).await?;
+                continue;
+            }
+        };
+
+        let strings = parse_string_table(&data);
+        debug!(key = %key, entry = %location.entry.name, strings = strings.len(), "extracted string table");
+        db.save_string_table(location, locale, table, strings)
+            .await?;
+        extracted += 1;
+    }
+
+    if extracted > 0 {
+        info!("Extracted {extracted} string tables");
+    }
+
+    Ok(extracted)
+}
+
+/// Reads the data of `entry` inside the WAD stored under `key`, no more than the size its file table lists.
+async fn read_entry(storage: &Storage, key: &str, entry: &WadEntry) -> std::io::Result<Vec<u8>> {
+    let reader = open_entry(storage, key, entry)
+        .await
+        .map_err(std::io::Error::other)?;
+
+    let mut data = Vec::with_capacity(entry.size as usize);
+    reader
+        .take(u64::from(entry.size))
+        .read_to_end(&mut data)
+        .await?;
+    Ok(data)
+}
@@ -0,0 +1,77 @@
+use crate::{
+    db::Database,
+    fetcher::{asset_fetcher::AssetFetcher, packed::tar_name},
+    revision::Asset,
+    wad::read_wad_header,
+};
+use futures_util::{StreamExt, stream};
+use std::collections::HashMap;
+use tracing::{debug, info, warn};
+
+/// Indexes the new WADs among the assets of `fetcher` from their headers alone, before they're downloaded.
+///
+/// The manifest lists the size and CRC of every WAD's header (everything before the entry data), so only those bytes
+/// are requested with a Range request and checked against `header_crc`. Like the downloads, the requests go to the
+/// patch server and then the mirrors, through the same client and bandwidth limit. WADs whose header can't be fetched
+/// or doesn't match are indexed after their download as usual. Returns how many WADs were indexed.
+pub async fn prefetch_wad_headers(
+    db: &Database,
+    fetcher: &AssetFetcher<'_>,
+    concurrent_requests: usize,
+) -> miette::Result<usize> {
+    let revision_name = fetcher.revision_name();
+    let headers: HashMap<&str, &Asset> = fetcher
+        .assets()
+        .iter()
+        // Packed WADs only exist upstream inside their tar, which has no header of its own
+        .filter(|asset| asset.header_size > 0 && tar_name(asset).is_none())
+        .map(|asset| (asset.file_name.as_str(), asset))
+        .collect();
+
+    // Only WADs this revision stores itself, shared ones were indexed with the revision that introduced them
+    let wads: Vec<&Asset> = db
+        .unindexed_wads()
+        .await?
+        .into_iter()
+        .filter(|(revision, _)| revision == revision_name)
+        .filter_map(|(_, file_name)| headers.get(file_name.as_str()).copied())
+        .collect();
+    if wads.is_empty() {
+        return Ok(0);
+    }
+
+    let fetches = wads.into_iter().map(|asset| async move {
+        let result = match fetcher.download_header(asset).await {
+            Ok(bytes) => read_wad_header(&mut bytes.as_slice()).await,
+            Err(e) => Err(e),
+        };
+        (asset, result)
+    });
+    let results: Vec<_> = stream::iter(fetches)
+        .buffer_unordered(concurrent_requests)
+        .collect()
+        .await;
+
+    let mut indexed = 0;
+    for (asset, result) in results {
+        match result {
+            Ok(archive) => {
+                debug!(file = %asset.file_name, entries = archive.entries.len(), "indexed WAD from its header");
+                db.save_wad_index(revision_name.to_string(), asset.file_name.clone(), archive)
+                    .await?;
+                indexed += 1;
+            }
+            Err(e) => warn!(
+                file = %asset.file_name,
+                "Failed to prefetch WAD header: {:?}",
+                miette::Report::new(e)
+            ),
+        }
+    }
+
+    if indexed > 0 {
+        info!("Indexed {indexed} WADs of {revision_name} from their headers");
+    }
+
+    Ok(indexed)
+}
@@ -0,0 +1,138 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A localized string, as stored in the `locale_strings` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocaleString {
+    /// Language directory the table is in, e.g. `English`
+    pub locale: String,
+    /// Name of the `.lang` file without the extension, e.g. `Spells`
+    pub table: String,
+    pub key: String,
+    pub text: String,
+    /// The revision that introduced the WAD the string was extracted from
+    pub revision: String,
+}
+
+/// Strings whose key or text matches a search.
+#[derive(Debug, Clone, Serialize)]
+pub struct StringSearchResults {
+    /// Ordered by locale, table and key
+    pub strings: Vec<LocaleString>,
+    /// Whether there were more matches than the limit
+    pub truncated: bool,
+}
+
+/// Strings that were added, removed or changed between two revisions.
+#[derive(Debug, Clone, Serialize)]
+pub struct StringDiff {
+    pub from: String,
+    pub to: String,
+    pub added: Vec<LocaleString>,
+    pub removed: Vec<LocaleString>,
+    pub modified: Vec<ModifiedString>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModifiedString {
+    pub locale: String,
+    pub table: String,
+    pub key: String,
+    pub from: String,
+    pub to: String,
+}
+
+impl StringDiff {
+    /// Strings are matched by locale, table and key.
+    pub fn new(from: String, to: String, old: &[LocaleString], new: &[LocaleString]) -> Self {
+        let id = |string: &LocaleString| {
+            (
+                string.locale.clone(),
+                string.table.clone(),
+                string.key.clone(),
+            )
+        };
+        let old_by_id: HashMap<_, &LocaleString> = old.iter().map(|s| (id(s), s)).collect();
+        let new_by_id: HashMap<_, &LocaleString> = new.iter().map(|s| (id(s), s)).collect();
+
+        let mut diff = Self {
+            from,
+            to,
+            added: Vec::new(),
+            removed: Vec::new(),
+            modified: Vec::new(),
+        };
+        for string in new {
+            match old_by_id.get(&id(string)) {
+                None => diff.added.push(string.clone()),
+                Some(previous) if previous.text != string.text => {
+                    diff.modified.push(ModifiedString {
+                        locale: string.locale.clone(),
+                        table: string.table.clone(),
+                        key: string.key.clone(),
+                        from: previous.text.clone(),
+                        to: string.text.clone(),
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .iter()
+            .filter(|string| !new_by_id.contains_key(&id(string)))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+/// Locale and table name of a string table inside a WAD, e.g. `("English", "Spells")` for
+/// `Locale/English/Spells.lang`. `None` for anything that isn't a `.lang` file.
+pub fn string_table_name(entry_name: &str) -> Option<(String, String)> {
+    let (directory, file) = entry_name.rsplit_once('/').unwrap_or(("", entry_name));
+    let table = file.strip_suffix(".lang")?;
+
+    // The language is the directory below `Locale`, or the closest one if there's no `Locale` directory
+    let locale = directory
+        .split('/')
+        .skip_while(|c| !c.eq_ignore_ascii_case("locale"))
+        .nth(1)
+        .or_else(|| directory.rsplit('/').find(|c| !c.is_empty()))
+        .unwrap_or_default();
+
+    Some((locale.to_string(), table.to_string()))
+}
+
+/// Parses a `.lang` string table into `(key, text)` pairs.
+///
+/// Tables are UTF-16LE with a BOM (UTF-8 is accepted as well). The first line names the table, it's followed by
+/// records of three lines each: the key, a comment for translators (usually empty), and the text.
+pub fn parse_string_table(data: &[u8]) -> Vec<(String, String)> {
+    let content = match data {
+        [0xFF, 0xFE, rest @ ..] => {
+            let units: Vec<u16> = rest
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    };
+
+    let lines: Vec<&str> = content
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .collect();
+
+    lines
+        .get(1..)
+        .unwrap_or_default()
+        .chunks(3)
+        .filter_map(|record| match record {
+            [key, _, text] if !key.is_empty() => Some((key.to_string(), text.to_string())),
+            _ => None,
+        })
+        .collect()
+}
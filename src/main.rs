@@ -1,28 +1,72 @@
 use crate::{
-    config::{AppConfig, FetcherConfig, PatchConfig, ServerConfig},
+    cli::{Cli, Command},
+    commands::{
+        analytics::analytics,
+        bundle::{export, import},
+        gc::gc,
+        import_v3::import_v3,
+        ingest::ingest,
+        plan::plan,
+        reconcile::reconcile,
+    },
+    config::{AppConfig, FetcherConfig, PatchConfig, ReplicationConfig, ServerConfig},
     db::Database,
-    fetcher::{asset_fetcher::AssetFetcher, manifest_fetcher::ManifestFetcher},
-    routes::{file::file, latest::get_latest_revision, revisions::get_revisions},
+    fetcher::{
+        asset_fetcher::{AssetFetcher, SharedDownloads},
+        filter::MirrorFilter,
+        manifest_fetcher::ManifestFetcher,
+        preflight::{FetchStatus, preflight},
+        replicator::Replicator,
+        wad_indexer::index_wads,
+        wad_prefetch::prefetch_wad_headers,
+    },
+    object_property::TypeList,
+    retention::collect_garbage,
+    revision::{Asset, Revision},
+    routes::{
+        analytics::{dedup_chains, file_churn, revision_growth},
+        file::file,
+        latest::get_latest_revision,
+        metadata::get_revision_metadata,
+        preview::{asset_preview, wad_entry_preview},
+        revisions::get_revisions,
+        search::search,
+        status::get_status,
+        strings::{lookup_string, search_strings, string_diff},
+        wad::{wad_diff, wad_entry},
+    },
+    storage::Storage,
     wizard_patcher::WizardPatcher,
 };
 use axum::{Router, routing::get};
+use clap::Parser;
 use miette::Result;
-use std::{net::SocketAddr, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::{net::TcpListener, time::sleep};
-use tracing::{info, level_filters::LevelFilter, warn};
+use tracing::{error, info, level_filters::LevelFilter, warn};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     EnvFilter, Layer, fmt::time::ChronoLocal, layer::SubscriberExt, util::SubscriberInitExt,
 };
 
+pub mod analytics;
 pub mod db;
+pub mod dds;
 pub mod errors;
+pub mod localization;
+pub mod object_property;
+pub mod search;
+pub mod storage;
 pub mod utils;
+pub mod wad;
 pub mod wizard_patcher;
 pub mod xml_parser;
 
+mod cli;
+mod commands;
 mod config;
 mod fetcher;
+mod retention;
 mod revision;
 mod routes;
 
@@ -30,27 +74,101 @@ mod routes;
 pub struct AppState {
     pub config: AppConfig,
     pub db: Database,
+    pub storage: Storage,
+    pub status: FetchStatus,
+    pub downloads: SharedDownloads,
+    /// Loaded from `[object_property]`, `None` if it isn't configured
+    pub types: Option<Arc<TypeList>>,
 }
 
 impl AppState {
-    pub fn new(config: AppConfig, db: Database) -> Self {
-        Self { config, db }
+    pub fn new(
+        config: AppConfig,
+        db: Database,
+        storage: Storage,
+        status: FetchStatus,
+        downloads: SharedDownloads,
+        types: Option<TypeList>,
+    ) -> Self {
+        Self {
+            config,
+            db,
+            storage,
+            status,
+            downloads,
+            types: types.map(Arc::new),
+        }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     // Load config
     let config = AppConfig::load()?;
 
     // Initialize logging
     let _logging = init_logging(&config);
 
+    match cli.command {
+        Some(Command::Plan { list }) => plan(&config, list).await,
+        Some(Command::Gc { dry_run }) => gc(&config, dry_run).await,
+        Some(Command::Reconcile { fix }) => reconcile(&config, fix).await,
+        Some(Command::Analytics { limit }) => analytics(&config, limit).await,
+        Some(Command::ImportV3 { directory }) => import_v3(&config, &directory).await,
+        Some(Command::Ingest {
+            revision,
+            source,
+            manifest,
+        }) => ingest(&config, &revision, &source, manifest.as_deref()).await,
+        Some(Command::Export { revisions, output }) => export(&config, &revisions, &output).await,
+        Some(Command::Import { bundle }) => import(&config, &bundle).await,
+        None => serve(config).await,
+    }
+}
+
+async fn serve(config: AppConfig) -> Result<()> {
     // Initialize database
     let db = Database::init(&config.database.path).await?;
 
-    let state = AppState::new(config.clone(), db.clone());
-    let tasks = tokio::join!(revision_checker(config, db), file_server(state));
+    // Initialize storage backend
+    let storage = Storage::from_config(&config)?;
+
+    let status = FetchStatus::default();
+    let downloads = SharedDownloads::new(&config.fetcher);
+
+    let types = match &config.object_property {
+        Some(object_property) => {
+            let types = TypeList::from_path(&object_property.types)?;
+            info!(
+                "Loaded {} classes from {}",
+                types.len(),
+                object_property.types
+            );
+            Some(types)
+        }
+        None => None,
+    };
+
+    let state = AppState::new(
+        config.clone(),
+        db.clone(),
+        storage.clone(),
+        status.clone(),
+        downloads.clone(),
+        types,
+    );
+    let tasks = match config.replication.clone() {
+        Some(replication) => tokio::join!(
+            replication_follower(config, replication, db, storage, status, downloads),
+            file_server(state)
+        ),
+        None => tokio::join!(
+            revision_checker(config, db, storage, status, downloads),
+            file_server(state)
+        ),
+    };
 
     tasks.0?;
     tasks.1?;
@@ -118,25 +236,35 @@ fn init_logging(config: &AppConfig) -> Option<WorkerGuard> {
     guard_to_return
 }
 
-async fn revision_checker(config: AppConfig, db: Database) -> miette::Result<()> {
+async fn revision_checker(
+    config: AppConfig,
+    db: Database,
+    storage: Storage,
+    status: FetchStatus,
+    downloads: SharedDownloads,
+) -> miette::Result<()> {
     let PatchConfig { host, port } = &config.patch;
     let FetcherConfig {
         fetch_interval,
-        concurrent_downloads,
-        save_directory,
+        filter,
         ..
     } = &config.fetcher;
+    let mirror_filter = filter.as_ref().map(MirrorFilter::new).transpose()?;
 
     loop {
         info!("Checking for a new revision @ {host}:{port}");
 
         let wizard_patcher = WizardPatcher::check_revision(host, port).await?;
-        let manifest_fetcher = ManifestFetcher::new(wizard_patcher.clone(), save_directory)?;
+        let manifest_fetcher = ManifestFetcher::new(wizard_patcher.clone(), storage.clone())?;
         manifest_fetcher.fetch_bin_manifest().await?;
         let new_assets = manifest_fetcher.fetch_xml_manifest().await?;
 
         match db
-            .insert_new_revision(wizard_patcher.revision.clone(), new_assets)
+            .insert_new_revision(
+                wizard_patcher.revision.clone(),
+                Some(wizard_patcher.url_prefix.clone()),
+                new_assets,
+            )
             .await
         {
             Ok(assets) => {
@@ -146,28 +274,232 @@ async fn revision_checker(config: AppConfig, db: Database) -> miette::Result<()>
                     assets.len()
                 );
 
-                let asset_fetched =
-                    AssetFetcher::new(wizard_patcher, concurrent_downloads, save_directory, assets)
-                        .unwrap();
-
-                asset_fetched.fetch_assets().await?;
+                // Filtered assets stay in the database, they are just marked as not mirrored
+                let (assets, skipped) = match &mirror_filter {
+                    Some(mirror_filter) => mirror_filter.split(assets),
+                    None => (assets, vec![]),
+                };
+
+                let revision_name = wizard_patcher.revision.name.clone();
+                if !skipped.is_empty() {
+                    info!("{} assets are excluded by the mirror filter", skipped.len());
+                    let file_names = skipped.into_iter().map(|a| a.file_name).collect();
+                    db.set_mirrored(revision_name.clone(), file_names, false)
+                        .await?;
+                }
+                let file_names = assets.iter().map(|a| a.file_name.clone()).collect();
+                db.set_mirrored(revision_name.clone(), file_names, true)
+                    .await?;
+
+                if !assets.is_empty() {
+                    let asset_fetcher = AssetFetcher::new(
+                        wizard_patcher,
+                        &config.fetcher,
+                        storage.clone(),
+                        assets,
+                        &downloads,
+                    )?;
+
+                    if config.fetcher.prefetch_wad_headers == Some(true)
+                        && let Err(e) = prefetch_wad_headers(
+                            &db,
+                            &asset_fetcher,
+                            config.fetcher.concurrent_downloads.get(),
+                        )
+                        .await
+                    {
+                        warn!(error = %e, "Failed to prefetch WAD headers");
+                    }
+
+                    if let Err(e) = preflight(
+                        &config.fetcher,
+                        &storage,
+                        &db,
+                        &revision_name,
+                        asset_fetcher.assets(),
+                    )
+                    .await
+                    {
+                        status.set_refused(&revision_name, &e);
+                        error!(
+                            "Refusing to fetch {revision_name}: {:?}",
+                            miette::Report::new(e)
+                        );
+                    } else {
+                        status.clear();
+
+                        let report = asset_fetcher.fetch_assets().await?;
+                        if !report.failed.is_empty() {
+                            warn!(
+                                "{} assets couldn't be downloaded from any source",
+                                report.failed.len()
+                            );
+                        }
+                        db.record_sources(revision_name, report.served_by).await?;
+                    }
+                }
             }
             Err(e) => {
                 warn!(error = %e, "Failed to insert new revision into database");
             }
         }
 
+        if let Err(e) =
+            fetch_newly_mirrored(&config, &db, &storage, &downloads, mirror_filter.as_ref()).await
+        {
+            warn!(error = %e, "Failed to download assets the mirror filter now includes");
+        }
+
+        if let Err(e) = index_wads(&db, &storage).await {
+            warn!(error = %e, "Failed to index WADs");
+        }
+
+        if let Some(retention) = config
+            .retention
+            .as_ref()
+            .filter(|retention| retention.automatic == Some(true))
+            && let Err(e) = collect_garbage(&db, &storage, retention, false).await
+        {
+            warn!(error = %e, "Garbage collection failed");
+        }
+
         info!("Done checking. Sleeping...");
         sleep(Duration::from_secs(*fetch_interval)).await;
     }
 }
 
+/// Downloads the blobs an earlier filter left out that `mirror_filter` includes now.
+///
+/// Later revisions deduplicate against those rows, so they are fetched for the revision that introduced them.
+async fn fetch_newly_mirrored(
+    config: &AppConfig,
+    db: &Database,
+    storage: &Storage,
+    downloads: &SharedDownloads,
+    mirror_filter: Option<&MirrorFilter>,
+) -> miette::Result<()> {
+    let mut included: HashMap<String, Vec<Asset>> = HashMap::new();
+    for (revision, record) in db.list_unmirrored_records().await? {
+        let asset = Asset::from(&record);
+        if mirror_filter.is_none_or(|filter| filter.is_mirrored(&asset)) {
+            included.entry(revision).or_default().push(asset);
+        }
+    }
+    if included.is_empty() {
+        return Ok(());
+    }
+
+    for revision in db.list_revision_info().await? {
+        let Some(assets) = included.remove(&revision.name) else {
+            continue;
+        };
+        let Some(url_prefix) = revision.url_prefix else {
+            warn!(
+                "Can't download {} newly included assets of {}, its URL prefix was never recorded",
+                assets.len(),
+                revision.name
+            );
+            continue;
+        };
+
+        info!(
+            "{} assets of {} are included by the mirror filter now",
+            assets.len(),
+            revision.name
+        );
+        preflight(&config.fetcher, storage, db, &revision.name, &assets).await?;
+
+        let file_names = assets.iter().map(|a| a.file_name.clone()).collect();
+        db.set_mirrored(revision.name.clone(), file_names, true)
+            .await?;
+
+        let wizard_patcher = WizardPatcher {
+            list_file_url: String::new(),
+            url_prefix,
+            revision: Revision {
+                name: revision.name.clone(),
+                number: revision.number,
+            },
+        };
+        let asset_fetcher = AssetFetcher::new(
+            wizard_patcher,
+            &config.fetcher,
+            storage.clone(),
+            assets,
+            downloads,
+        )?;
+
+        let report = asset_fetcher.fetch_assets().await?;
+        if !report.failed.is_empty() {
+            warn!(
+                "{} assets of {} couldn't be downloaded from any source",
+                report.failed.len(),
+                revision.name
+            );
+        }
+        db.record_sources(revision.name, report.served_by).await?;
+    }
+
+    Ok(())
+}
+
+async fn replication_follower(
+    config: AppConfig,
+    replication: ReplicationConfig,
+    db: Database,
+    storage: Storage,
+    status: FetchStatus,
+    downloads: SharedDownloads,
+) -> miette::Result<()> {
+    let poll_interval = replication
+        .poll_interval
+        .unwrap_or(config.fetcher.fetch_interval);
+    let replicator = Replicator::new(
+        &replication.leader,
+        &config,
+        db.clone(),
+        storage.clone(),
+        status,
+        downloads,
+    )?;
+
+    loop {
+        info!("Checking for a new revision @ {}", replication.leader);
+
+        if let Err(e) = replicator.sync().await {
+            warn!(error = %e, "Failed to replicate from the leader");
+        }
+        if let Err(e) = index_wads(&db, &storage).await {
+            warn!(error = %e, "Failed to index WADs");
+        }
+
+        info!("Done checking. Sleeping...");
+        sleep(Duration::from_secs(poll_interval)).await;
+    }
+}
+
 async fn file_server(state: AppState) -> miette::Result<()> {
     let ServerConfig { endpoint, .. } = &state.config.server;
 
     let app = Router::new()
         .route("/revisions", get(get_revisions))
         .route("/latest", get(get_latest_revision))
+        .route("/status", get(get_status))
+        .route("/metadata/{revision}", get(get_revision_metadata))
+        .route("/search", get(search))
+        .route("/analytics/churn", get(file_churn))
+        .route("/analytics/growth", get(revision_growth))
+        .route("/analytics/dedup", get(dedup_chains))
+        .route("/strings/{revision}", get(search_strings))
+        .route("/strings/{revision}/{key}", get(lookup_string))
+        .route("/diff/{from}/{to}/strings", get(string_diff))
+        .route("/diff/{from}/{to}/wad/{wad_name}", get(wad_diff))
+        .route(
+            "/preview/{revision}/wad/{wad_name}/{*inner_path}",
+            get(wad_entry_preview),
+        )
+        .route("/preview/{revision}/{*file_path}", get(asset_preview))
+        .route("/{revision}/wad/{wad_name}/{*inner_path}", get(wad_entry))
         .route("/{revision}/{*file_path}", get(file))
         .with_state(state.clone());
 
@@ -0,0 +1,543 @@
+//! Decoder for KingsIsle's ObjectProperty serialization, the binary format of most game data files inside WADs
+//! (they start with `BINd`).
+//!
+//! The format isn't self-describing, decoding needs the class and property definitions of the client the file was
+//! made for, see [`TypeList`].
+
+mod reader;
+mod types;
+
+pub use types::{ClassDef, PropertyDef, TypeList};
+
+use crate::errors::ObjectPropertyError;
+use flate2::read::ZlibDecoder;
+use reader::BitReader;
+use serde_json::{Map, Value};
+use std::io::Read;
+
+/// Magic of serialized game data files, followed by the serializer flags
+const MAGIC: &[u8; 4] = b"BINd";
+/// Nested objects deeper than this are treated as garbage instead of recursing further
+const MAX_DEPTH: usize = 128;
+/// Limit for the decompressed size a compressed file may claim
+const MAX_DECOMPRESSED_SIZE: u32 = 256 * 1024 * 1024;
+
+/// The serializer flags are stored at the start of the data
+pub const STATEFUL_FLAGS: u32 = 1 << 0;
+/// Length prefixes are 7 or 31 bits with a leading bit telling which, instead of 16 (strings) or 32 (containers)
+pub const COMPACT_LENGTH_PREFIXES: u32 = 1 << 1;
+/// Enums are written as their names instead of their values
+pub const HUMAN_READABLE_ENUMS: u32 = 1 << 2;
+/// The object may be zlib-compressed
+pub const WITH_COMPRESSION: u32 = 1 << 3;
+
+#[derive(Debug, Clone)]
+pub struct DeserializerOptions {
+    /// Serializer flags, overridden by the data itself for `BINd` files
+    pub flags: u32,
+    /// Shallow data lists the properties back to back, deep data (the default) prefixes each with its size and hash
+    pub shallow: bool,
+    /// Flags a property needs to be part of shallow data
+    pub property_mask: u32,
+    /// Decode objects of unknown classes as `null` instead of failing, only possible for deep data
+    pub skip_unknown_types: bool,
+}
+
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        Self {
+            flags: 0,
+            shallow: false,
+            property_mask: 0x18,
+            skip_unknown_types: false,
+        }
+    }
+}
+
+/// Decodes serialized ObjectProperty data into JSON.
+///
+/// Objects become maps of their property names with the class name under `$__type`, containers become arrays and
+/// enum values become their names where the type list knows them.
+pub fn deserialize(
+    data: &[u8],
+    types: &TypeList,
+    options: &DeserializerOptions,
+) -> Result<Value, ObjectPropertyError> {
+    let mut flags = options.flags;
+    let data = match data.strip_prefix(MAGIC) {
+        Some(rest) => {
+            flags |= STATEFUL_FLAGS;
+            rest
+        }
+        None => data,
+    };
+
+    let mut decompressed = Vec::new();
+    let mut reader = BitReader::new(data);
+    if flags & STATEFUL_FLAGS != 0 {
+        flags = reader.u32()?;
+    }
+    if flags & WITH_COMPRESSION != 0 && reader.u8()? != 0 {
+        let size = reader.u32()?;
+        if size > MAX_DECOMPRESSED_SIZE {
+            return Err(ObjectPropertyError::Corrupt(
+                "decompressed size is too large",
+            ));
+        }
+
+        let compressed = reader.bytes(reader.remaining_bits() / 8)?;
+        decompressed.reserve(size as usize);
+        ZlibDecoder::new(compressed)
+            .take(u64::from(size))
+            .read_to_end(&mut decompressed)
+            .map_err(ObjectPropertyError::Decompress)?;
+        reader = BitReader::new(&decompressed);
+    }
+
+    let deserializer = Deserializer {
+        types,
+        options,
+        flags,
+    };
+    deserializer.object(&mut reader, 0)
+}
+
+struct Deserializer<'a> {
+    types: &'a TypeList,
+    options: &'a DeserializerOptions,
+    flags: u32,
+}
+
+impl Deserializer<'_> {
+    fn object(&self, reader: &mut BitReader, depth: usize) -> Result<Value, ObjectPropertyError> {
+        if depth > MAX_DEPTH {
+            return Err(ObjectPropertyError::Corrupt(
+                "objects are nested too deeply",
+            ));
+        }
+
+        let hash = reader.u32()?;
+        if hash == 0 {
+            return Ok(Value::Null);
+        }
+
+        let class = match self.types.class(hash) {
+            Some(class) => class,
+            None if self.options.skip_unknown_types && !self.options.shallow => {
+                // The size counts from its own start, which is 32 bits back once it's read
+                let start = reader.position();
+                let size = reader.u32()? as usize;
+                reader.seek(start + size)?;
+                return Ok(Value::Null);
+            }
+            None => return Err(ObjectPropertyError::UnknownType(hash)),
+        };
+
+        let mut object = Map::new();
+        object.insert("$__type".to_string(), Value::from(class.name.as_str()));
+
+        if self.options.shallow {
+            let mask = self.options.property_mask;
+            for property in class
+                .properties
+                .iter()
+                .filter(|property| property.flags & mask == mask)
+            {
+                let value = self.property(reader, property, depth)?;
+                object.insert(property.name.clone(), value);
+            }
+
+            return Ok(Value::Object(object));
+        }
+
+        let start = reader.position();
+        let end = start + reader.u32()? as usize;
+        while reader.position() < end {
+            let property_start = reader.position();
+            let property_size = reader.u32()? as usize;
+            if property_size == 0 {
+                return Err(ObjectPropertyError::Corrupt("property of size zero"));
+            }
+            let property_hash = reader.u32()?;
+
+            // Properties the type list doesn't know (e.g. from a newer client) are skipped
+            if let Some(property) = class.property(property_hash) {
+                let value = self.property(reader, property, depth)?;
+                object.insert(property.name.clone(), value);
+            }
+            reader.seek(property_start + property_size)?;
+        }
+        reader.seek(end)?;
+
+        Ok(Value::Object(object))
+    }
+
+    fn property(
+        &self,
+        reader: &mut BitReader,
+        property: &PropertyDef,
+        depth: usize,
+    ) -> Result<Value, ObjectPropertyError> {
+        if !property.dynamic {
+            return self.value(reader, property, depth);
+        }
+
+        let length = self.length_prefix(reader, false)?;
+        // Every element takes at least a bit, so longer lengths can only come from garbage
+        if length > reader.remaining_bits() {
+            return Err(ObjectPropertyError::Corrupt(
+                "container is longer than the data",
+            ));
+        }
+
+        (0..length)
+            .map(|_| self.value(reader, property, depth))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array)
+    }
+
+    fn value(
+        &self,
+        reader: &mut BitReader,
+        property: &PropertyDef,
+        depth: usize,
+    ) -> Result<Value, ObjectPropertyError> {
+        if !property.enum_options.is_empty() {
+            return self.enum_value(reader, property);
+        }
+
+        let value = match property.type_name.as_str() {
+            "bool" => Value::from(reader.bool()?),
+            "char" | "signed char" => Value::from(reader.u8()? as i8),
+            "unsigned char" => Value::from(reader.u8()?),
+            "short" => Value::from(reader.u16()? as i16),
+            "unsigned short" | "wchar_t" => Value::from(reader.u16()?),
+            "int" | "long" => Value::from(reader.u32()? as i32),
+            "unsigned int" | "unsigned long" => Value::from(reader.u32()?),
+            "__int64" => Value::from(reader.u64()? as i64),
+            "unsigned __int64" | "gid" => Value::from(reader.u64()?),
+            "float" => Value::from(reader.f32()?),
+            "double" => Value::from(reader.f64()?),
+            "std::string" => Value::from(self.string(reader)?),
+            "std::wstring" => Value::from(self.wide_string(reader)?),
+            "s24" => Value::from(sign_extend(reader.bits(24)?, 24)),
+            "u24" => Value::from(reader.bits(24)?),
+            "class Color" => {
+                let [b, g, r, a] = reader.bytes(4)?.try_into().expect("slice has 4 bytes");
+                serde_json::json!({ "r": r, "g": g, "b": b, "a": a })
+            }
+            "class Vector3D" | "class Euler" => floats(reader, 3)?,
+            "class Quaternion" | "class Rect<float>" => floats(reader, 4)?,
+            "class Matrix3x3" => floats(reader, 9)?,
+            "class Point<float>" | "class Size<float>" => floats(reader, 2)?,
+            "class Point<int>" | "class Size<int>" => ints(reader, 2)?,
+            "class Rect<int>" => ints(reader, 4)?,
+            name => match bit_field(name) {
+                Some((bits, true)) => Value::from(sign_extend(reader.bits(bits)?, bits)),
+                Some((bits, false)) => Value::from(reader.bits(bits)?),
+                // Everything else is another object, which carries its own type hash
+                None => self.object(reader, depth + 1)?,
+            },
+        };
+
+        Ok(value)
+    }
+
+    /// Enums are the name of their value if it has one, a `|`-joined list of names if the value is a combination of
+    /// flags, and the plain number otherwise.
+    fn enum_value(
+        &self,
+        reader: &mut BitReader,
+        property: &PropertyDef,
+    ) -> Result<Value, ObjectPropertyError> {
+        if self.flags & HUMAN_READABLE_ENUMS != 0 {
+            return Ok(Value::from(self.string(reader)?));
+        }
+
+        let value = i64::from(reader.u32()?);
+        let options = &property.enum_options;
+        if let Some((name, _)) = options.iter().find(|(_, option)| *option == value) {
+            return Ok(Value::from(name.as_str()));
+        }
+
+        let set: Vec<&(String, i64)> = options
+            .iter()
+            .filter(|(_, option)| *option > 0 && value & option == *option)
+            .collect();
+        let covered = set.iter().fold(0, |covered, (_, option)| covered | option);
+        if value != 0 && covered == value {
+            let names: Vec<&str> = set.iter().map(|(name, _)| name.as_str()).collect();
+            return Ok(Value::from(names.join("|")));
+        }
+
+        Ok(Value::from(value))
+    }
+
+    fn length_prefix(
+        &self,
+        reader: &mut BitReader,
+        string: bool,
+    ) -> Result<usize, ObjectPropertyError> {
+        let length = if self.flags & COMPACT_LENGTH_PREFIXES != 0 {
+            let large = reader.bool()?;
+            reader.bits(if large { 31 } else { 7 })?
+        } else if string {
+            u64::from(reader.u16()?)
+        } else {
+            u64::from(reader.u32()?)
+        };
+
+        Ok(length as usize)
+    }
+
+    fn string(&self, reader: &mut BitReader) -> Result<String, ObjectPropertyError> {
+        let length = self.length_prefix(reader, true)?;
+        Ok(String::from_utf8_lossy(reader.bytes(length)?).into_owned())
+    }
+
+    fn wide_string(&self, reader: &mut BitReader) -> Result<String, ObjectPropertyError> {
+        let length = self.length_prefix(reader, true)?;
+        let units: Vec<u16> = reader
+            .bytes(length * 2)?
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
+
+/// `bi2`..`bi7` and `bui2`..`bui7`, returning the width and whether it's signed.
+fn bit_field(type_name: &str) -> Option<(u32, bool)> {
+    let (digits, signed) = match type_name.strip_prefix("bui") {
+        Some(digits) => (digits, false),
+        None => (type_name.strip_prefix("bi")?, true),
+    };
+
+    match digits.parse() {
+        Ok(bits @ 2..=7) => Some((bits, signed)),
+        _ => None,
+    }
+}
+
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+fn floats(reader: &mut BitReader, count: usize) -> Result<Value, ObjectPropertyError> {
+    (0..count)
+        .map(|_| reader.f32().map(Value::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Value::Array)
+}
+
+fn ints(reader: &mut BitReader, count: usize) -> Result<Value, ObjectPropertyError> {
+    (0..count)
+        .map(|_| reader.u32().map(|value| Value::from(value as i32)))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Value::Array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ROOT: u32 = 100;
+    const CHILD: u32 = 200;
+
+    fn types() -> TypeList {
+        let json = json!({
+            "version": 2,
+            "classes": {
+                "100": {
+                    "name": "class Root",
+                    "properties": {
+                        "m_name": { "type": "std::string", "id": 0, "hash": 11, "flags": 0x18 },
+                        "m_count": { "type": "int", "id": 1, "hash": 12, "flags": 0x18 },
+                        "m_child": { "type": "class SharedPointer<class Child>", "id": 2, "hash": 13, "flags": 0x18 },
+                        "m_mode": {
+                            "type": "enum Mode", "id": 3, "hash": 14, "flags": 0x18,
+                            "enum_options": { "A": 1, "B": 2, "C": 4, "__DEFAULT": "A" }
+                        },
+                        "m_bytes": { "type": "unsigned char", "id": 4, "hash": 15, "flags": 0x18, "dynamic": true },
+                        "m_hidden": { "type": "int", "id": 5, "hash": 16, "flags": 0 }
+                    }
+                },
+                "200": {
+                    "name": "class Child",
+                    "properties": {
+                        "m_value": { "type": "float", "id": 0, "hash": 21, "flags": 0x18 }
+                    }
+                }
+            }
+        });
+
+        TypeList::from_slice(json.to_string().as_bytes()).unwrap()
+    }
+
+    /// A deep object: its hash and size, then every property with its own size and hash. Sizes are in bits.
+    fn deep(hash: u32, properties: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (hash, value) in properties {
+            body.extend((((8 + value.len()) * 8) as u32).to_le_bytes());
+            body.extend(hash.to_le_bytes());
+            body.extend(value);
+        }
+
+        let mut bytes = hash.to_le_bytes().to_vec();
+        bytes.extend((((4 + body.len()) * 8) as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = (value.len() as u16).to_le_bytes().to_vec();
+        bytes.extend(value.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn decodes_deep_objects() {
+        let child = deep(CHILD, &[(21, 1.5f32.to_le_bytes().to_vec())]);
+        let mut bytes = 3u32.to_le_bytes().to_vec();
+        bytes.extend([7, 8, 9]);
+        let data = deep(
+            ROOT,
+            &[
+                (11, string("Malistaire")),
+                (12, (-5i32).to_le_bytes().to_vec()),
+                // Unknown to the type list, skipped by its size
+                (99, vec![1, 2, 3]),
+                (13, child),
+                (15, bytes),
+            ],
+        );
+
+        let value = deserialize(&data, &types(), &DeserializerOptions::default()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "$__type": "class Root",
+                "m_name": "Malistaire",
+                "m_count": -5,
+                "m_child": { "$__type": "class Child", "m_value": 1.5 },
+                "m_bytes": [7, 8, 9],
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_shallow_objects() {
+        let mut data = ROOT.to_le_bytes().to_vec();
+        data.extend(string("Gamma"));
+        data.extend(42i32.to_le_bytes());
+        // Null child
+        data.extend(0u32.to_le_bytes());
+        data.extend(2u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        // `m_hidden` lacks the property mask and isn't part of the data
+
+        let options = DeserializerOptions {
+            shallow: true,
+            ..Default::default()
+        };
+        let value = deserialize(&data, &types(), &options).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "$__type": "class Root",
+                "m_name": "Gamma",
+                "m_count": 42,
+                "m_child": null,
+                "m_mode": "B",
+                "m_bytes": [],
+            })
+        );
+    }
+
+    #[test]
+    fn reads_compact_length_prefixes_from_bind_flags() {
+        let mut data = MAGIC.to_vec();
+        data.extend(COMPACT_LENGTH_PREFIXES.to_le_bytes());
+        data.extend(ROOT.to_le_bytes());
+        // Short prefix: a clear bit, then 7 bits of length
+        data.push(3 << 1);
+        data.extend(b"Bat");
+        data.extend(1i32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        // Long prefix: a set bit, then 31 bits of length
+        data.extend(((2u32 << 1) | 1).to_le_bytes());
+        data.extend([4, 5]);
+
+        let options = DeserializerOptions {
+            shallow: true,
+            ..Default::default()
+        };
+        let value = deserialize(&data, &types(), &options).unwrap();
+        assert_eq!(value["m_name"], "Bat");
+        assert_eq!(value["m_mode"], "A");
+        assert_eq!(value["m_bytes"], json!([4, 5]));
+    }
+
+    #[test]
+    fn names_enum_flag_combinations() {
+        let mode = |value: u32| {
+            let data = deep(ROOT, &[(14, value.to_le_bytes().to_vec())]);
+            deserialize(&data, &types(), &DeserializerOptions::default()).unwrap()["m_mode"].clone()
+        };
+
+        assert_eq!(mode(4), "C");
+        assert_eq!(mode(6), "B|C");
+        assert_eq!(mode(7), "A|B|C");
+        // Not covered by the options
+        assert_eq!(mode(9), 9);
+        assert_eq!(mode(0), 0);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = deep(ROOT, &[(11, string("Malistaire"))]);
+
+        for length in [0, 3, 8, 15, data.len() - 1] {
+            let result = deserialize(&data[..length], &types(), &DeserializerOptions::default());
+            assert!(result.is_err(), "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let garbage: Vec<u8> = (0..64u32).map(|i| (i * 37 % 251) as u8).collect();
+        for options in [
+            DeserializerOptions::default(),
+            DeserializerOptions {
+                shallow: true,
+                ..Default::default()
+            },
+            DeserializerOptions {
+                skip_unknown_types: true,
+                ..Default::default()
+            },
+        ] {
+            assert!(deserialize(&garbage, &types(), &options).is_err());
+        }
+
+        // A container claiming far more elements than there is data
+        let mut data = ROOT.to_le_bytes().to_vec();
+        data.extend(string(""));
+        data.extend(0i32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.extend(u32::MAX.to_le_bytes());
+        let options = DeserializerOptions {
+            shallow: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            deserialize(&data, &types(), &options),
+            Err(ObjectPropertyError::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::errors::ObjectPropertyError;
+
+/// Reads the bit stream ObjectProperty data is written as: bits are consumed least significant first, everything
+/// wider than a bit field starts on a byte boundary.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining_bits(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.position)
+    }
+
+    pub fn seek(&mut self, position: usize) -> Result<(), ObjectPropertyError> {
+        if position > self.data.len() * 8 {
+            return Err(ObjectPropertyError::UnexpectedEnd);
+        }
+        self.position = position;
+
+        Ok(())
+    }
+
+    pub fn realign(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+
+    pub fn bits(&mut self, count: u32) -> Result<u64, ObjectPropertyError> {
+        debug_assert!(count <= 64);
+        if self.remaining_bits() < count as usize {
+            return Err(ObjectPropertyError::UnexpectedEnd);
+        }
+
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data[self.position / 8];
+            let bit = (byte >> (self.position % 8)) & 1;
+            value |= u64::from(bit) << i;
+            self.position += 1;
+        }
+
+        Ok(value)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, ObjectPropertyError> {
+        Ok(self.bits(1)? == 1)
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], ObjectPropertyError> {
+        self.realign();
+        let start = self.position / 8;
+        let bytes = self
+            .data
+            .get(start..start + count)
+            .ok_or(ObjectPropertyError::UnexpectedEnd)?;
+        self.position += count * 8;
+
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, ObjectPropertyError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, ObjectPropertyError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, ObjectPropertyError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, ObjectPropertyError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, ObjectPropertyError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, ObjectPropertyError> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ObjectPropertyError> {
+        Ok(self.bytes(N)?.try_into().expect("slice has N bytes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_bits_least_significant_first() {
+        let mut reader = BitReader::new(&[0b1011_0110, 0xFF]);
+
+        assert!(!reader.bool().unwrap());
+        assert_eq!(reader.bits(3).unwrap(), 0b011);
+        assert_eq!(reader.bits(6).unwrap(), 0b11_1011);
+        assert_eq!(reader.remaining_bits(), 6);
+    }
+
+    #[test]
+    fn aligns_wider_values_to_bytes() {
+        let mut reader = BitReader::new(&[0x01, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12]);
+
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.u16().unwrap(), 0x1234);
+        assert_eq!(reader.position(), 24);
+        assert_eq!(reader.u32().unwrap(), 0x1234_5678);
+    }
+
+    #[test]
+    fn fails_at_the_end_of_the_data() {
+        let mut reader = BitReader::new(&[0xAA, 0xBB, 0xCC]);
+
+        assert!(matches!(
+            reader.u32(),
+            Err(ObjectPropertyError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            reader.bits(25),
+            Err(ObjectPropertyError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            reader.seek(25),
+            Err(ObjectPropertyError::UnexpectedEnd)
+        ));
+
+        reader.seek(16).unwrap();
+        assert_eq!(reader.u8().unwrap(), 0xCC);
+        assert!(matches!(
+            reader.u8(),
+            Err(ObjectPropertyError::UnexpectedEnd)
+        ));
+        assert!(reader.bytes(0).unwrap().is_empty());
+    }
+}
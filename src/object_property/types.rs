@@ -0,0 +1,139 @@
+use crate::errors::ObjectPropertyError;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+/// Class and property definitions of a game client, keyed by type hash.
+///
+/// Loaded from a JSON type dump in the format of wiztype/katsuba (version 2):
+/// `{"version": 2, "classes": {"<hash>": {"name": "class X", "properties": {"m_name": {...}}}}}`.
+#[derive(Debug, Clone, Default)]
+pub struct TypeList {
+    classes: HashMap<u32, ClassDef>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassDef {
+    pub name: String,
+    /// In serialization order
+    pub properties: Vec<PropertyDef>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PropertyDef {
+    pub name: String,
+    /// C++ type name, e.g. `std::string` or `class SharedPointer<class Foo>`
+    pub type_name: String,
+    pub hash: u32,
+    pub flags: u32,
+    /// Containers with a length prefix (`std::vector`, `std::list`)
+    pub dynamic: bool,
+    /// Names of the enum's values, empty for everything that isn't an enum
+    pub enum_options: Vec<(String, i64)>,
+}
+
+impl ClassDef {
+    pub fn property(&self, hash: u32) -> Option<&PropertyDef> {
+        self.properties
+            .iter()
+            .find(|property| property.hash == hash)
+    }
+}
+
+#[derive(Deserialize)]
+struct RawTypeList {
+    version: u32,
+    classes: HashMap<String, RawClass>,
+}
+
+#[derive(Deserialize)]
+struct RawClass {
+    name: String,
+    #[serde(default)]
+    properties: HashMap<String, RawProperty>,
+}
+
+#[derive(Deserialize)]
+struct RawProperty {
+    #[serde(rename = "type")]
+    type_name: String,
+    id: u32,
+    hash: u32,
+    #[serde(default)]
+    flags: u32,
+    #[serde(default)]
+    dynamic: bool,
+    #[serde(default)]
+    enum_options: HashMap<String, serde_json::Value>,
+}
+
+impl TypeList {
+    pub fn from_slice(json: &[u8]) -> Result<Self, ObjectPropertyError> {
+        let raw: RawTypeList =
+            serde_json::from_slice(json).map_err(ObjectPropertyError::InvalidTypeList)?;
+        if raw.version != 2 {
+            return Err(ObjectPropertyError::UnsupportedTypeList(raw.version));
+        }
+
+        let mut classes = HashMap::with_capacity(raw.classes.len());
+        for (hash, class) in raw.classes {
+            let Ok(hash) = hash.parse() else {
+                return Err(ObjectPropertyError::InvalidTypeHash(hash));
+            };
+
+            let mut properties: Vec<(u32, PropertyDef)> = class
+                .properties
+                .into_iter()
+                .map(|(name, property)| {
+                    // Some options are strings like `__DEFAULT`, those never show up in serialized data
+                    let mut enum_options: Vec<(String, i64)> = property
+                        .enum_options
+                        .into_iter()
+                        .filter_map(|(name, value)| value.as_i64().map(|value| (name, value)))
+                        .collect();
+                    enum_options.sort_by_key(|(_, value)| *value);
+
+                    let def = PropertyDef {
+                        name,
+                        type_name: property.type_name,
+                        hash: property.hash,
+                        flags: property.flags,
+                        dynamic: property.dynamic,
+                        enum_options,
+                    };
+                    (property.id, def)
+                })
+                .collect();
+            properties.sort_by_key(|(id, _)| *id);
+
+            classes.insert(
+                hash,
+                ClassDef {
+                    name: class.name,
+                    properties: properties.into_iter().map(|(_, def)| def).collect(),
+                },
+            );
+        }
+
+        Ok(Self { classes })
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ObjectPropertyError> {
+        let path = path.as_ref();
+        let json = std::fs::read(path)
+            .map_err(|e| ObjectPropertyError::ReadTypeList(e, path.to_path_buf()))?;
+
+        Self::from_slice(&json)
+    }
+
+    pub fn class(&self, hash: u32) -> Option<&ClassDef> {
+        self.classes.get(&hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    config::RetentionConfig,
+    db::{BlobMove, Database, RevisionInfo},
+    storage::{Storage, StorageBackend, asset_key, zstd_key},
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tracing::{debug, info, warn};
+
+/// Outcome of a garbage collection pass.
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub kept: Vec<String>,
+    pub removed: Vec<String>,
+    /// Blobs that moved to a retained revision, because it still refers to them
+    pub moved: usize,
+    pub deleted_objects: usize,
+    pub freed_bytes: u64,
+}
+
+/// Splits `revisions` (newest first) into the names of the revisions to keep and to remove.
+pub fn select_revisions(
+    revisions: &[RevisionInfo],
+    config: &RetentionConfig,
+) -> (Vec<String>, Vec<String>) {
+    if config.keep_last.is_none() && config.keep_since.is_none() {
+        return (revisions.iter().map(|r| r.name.clone()).collect(), vec![]);
+    }
+
+    let pinned = config.pinned.as_deref().unwrap_or_default();
+    let (kept, removed): (Vec<_>, Vec<_>) =
+        revisions.iter().enumerate().partition(|(idx, revision)| {
+            *idx == 0
+                || pinned.contains(&revision.name)
+                || config.keep_last.is_some_and(|keep| *idx < keep.get())
+                || config.keep_since.is_some_and(|since| {
+                    // Revisions from before discovery times were recorded can't be dated, so they stay
+                    revision
+                        .discovered_at
+                        .is_none_or(|discovered| discovered.date() >= since)
+                })
+        });
+
+    let names = |revisions: Vec<(usize, &RevisionInfo)>| {
+        revisions
+            .into_iter()
+            .map(|(_, revision)| revision.name.clone())
+            .collect()
+    };
+    (names(kept), names(removed))
+}
+
+/// Removes every revision the retention policy doesn't keep, along with the blobs no retained revision refers to.
+///
+/// Blobs that retained revisions still point at (through `origin_revision`) are moved to the oldest of them first,
+/// so nothing that is still served ever disappears. With `dry_run`, only the report is computed.
+pub async fn collect_garbage(
+    db: &Database,
+    storage: &Storage,
+    config: &RetentionConfig,
+    dry_run: bool,
+) -> miette::Result<GcReport> {
+    let revisions = db.list_revision_info().await?;
+    let (kept, removed) = select_revisions(&revisions, config);
+
+    let mut report = GcReport {
+        kept,
+        removed: removed.clone(),
+        ..Default::default()
+    };
+    if removed.is_empty() {
+        return Ok(report);
+    }
+
+    let plan = db.plan_garbage_collection(removed.clone()).await?;
+    report.moved = plan.moves.len();
+    debug!(
+        moves = plan.moves.len(),
+        unreferenced = plan.unreferenced.len(),
+        "planned garbage collection"
+    );
+
+    let moved_keys: HashSet<String> = plan
+        .moves
+        .iter()
+        .flat_map(|blob| {
+            let key = asset_key(&blob.from, &blob.file_name);
+            let tar_key = blob
+                .tar_file_name
+                .as_ref()
+                .map(|tar| asset_key(&blob.from, tar));
+            [Some(zstd_key(&key)), Some(key), tar_key]
+        })
+        .flatten()
+        .collect();
+
+    if !dry_run {
+        let numbers = revisions
+            .iter()
+            .map(|revision| (revision.name.as_str(), revision.number))
+            .collect();
+        let renamed = move_blobs(storage, &plan.moves, &numbers).await?;
+
+        if let Err(e) = db.remove_revisions(removed.clone(), plan.moves).await {
+            // Put the blobs back, the database still points at their old location
+            for (from, to) in renamed.into_iter().rev() {
+                if let Err(e) = storage.rename(&to, &from).await {
+                    warn!(error = %e, key = %from, "failed to move blob back");
+                }
+            }
+            return Err(e);
+        }
+    }
+
+    // Whatever is left below a removed revision (blobs, manifests, stale .part files) is unreferenced now
+    for revision in &removed {
+        for object in storage.list(&format!("{revision}/")).await? {
+            if dry_run && moved_keys.contains(&object.key) {
+                continue;
+            }
+
+            if !dry_run {
+                storage.delete(&object.key).await?;
+            }
+            report.deleted_objects += 1;
+            report.freed_bytes += object.size;
+        }
+    }
+
+    if !dry_run {
+        info!(
+            "Removed {} revisions, moved {} blobs, deleted {} objects",
+            report.removed.len(),
+            report.moved,
+            report.deleted_objects
+        );
+    }
+
+    Ok(report)
+}
+
+/// Moves the stored representation (plain or zstd) of each blob, returning the `(from, to)` keys that were moved.
+///
+/// Tars the blobs were delivered in move along once, to the newest revision that takes over one of their members.
+/// That's where `get_revision_for_tar` looks for them, unless the new origin downloaded its own copy.
+async fn move_blobs(
+    storage: &Storage,
+    moves: &[BlobMove],
+    numbers: &HashMap<&str, i64>,
+) -> miette::Result<Vec<(String, String)>> {
+    let number = |revision: &str| numbers.get(revision).copied().unwrap_or_default();
+
+    let mut keys = Vec::new();
+    let mut tars: BTreeMap<(&str, &str), &str> = BTreeMap::new();
+    for blob in moves {
+        let from = asset_key(&blob.from, &blob.file_name);
+        let to = asset_key(&blob.to, &blob.file_name);
+        keys.push((zstd_key(&from), zstd_key(&to)));
+        keys.push((from, to));
+
+        if let Some(tar) = blob
+            .tar_file_name
+            .as_deref()
+            .filter(|tar| *tar != blob.file_name)
+        {
+            let target = tars.entry((&blob.from, tar)).or_insert(&blob.to);
+            if number(&blob.to) > number(target) {
+                *target = &blob.to;
+            }
+        }
+    }
+    keys.extend(
+        tars.into_iter()
+            .map(|((from, tar), to)| (asset_key(from, tar), asset_key(to, tar))),
+    );
+
+    let mut renamed = Vec::new();
+    for (from, to) in keys {
+        // Blobs never exist at their new origin, but a tar may have been downloaded there again
+        let movable = match (storage.exists(&from).await, storage.exists(&to).await) {
+            (Ok(from_exists), Ok(to_exists)) => from_exists && !to_exists,
+            (Err(e), _) | (_, Err(e)) => return Err(rollback(storage, renamed, e.into()).await),
+        };
+        if !movable {
+            continue;
+        }
+
+        if let Err(e) = storage.rename(&from, &to).await {
+            return Err(rollback(storage, renamed, e.into()).await);
+        }
+        renamed.push((from, to));
+    }
+
+    Ok(renamed)
+}
+
+async fn rollback(
+    storage: &Storage,
+    renamed: Vec<(String, String)>,
+    error: miette::Report,
+) -> miette::Report {
+    for (from, to) in renamed.into_iter().rev() {
+        if let Err(e) = storage.rename(&to, &from).await {
+            warn!(error = %e, key = %from, "failed to move blob back");
+        }
+    }
+
+    error
+}
@@ -0,0 +1,55 @@
+use crate::{AppState, errors::RouteError, utils::ConnectionAddr};
+use axum::{
+    Json,
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use tracing::debug;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsQuery {
+    /// How many files or chains to list
+    limit: Option<usize>,
+}
+
+impl AnalyticsQuery {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+}
+
+/// The files with the most distinct versions.
+pub async fn file_churn(
+    State(state): State<AppState>,
+    Query(query): Query<AnalyticsQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /analytics/churn from {addr}");
+
+    Ok(Json(state.db.most_changed_files(query.limit()).await?))
+}
+
+/// Total size and unique bytes of every revision.
+pub async fn revision_growth(
+    State(state): State<AppState>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /analytics/growth from {addr}");
+
+    Ok(Json(state.db.revision_growth().await?))
+}
+
+/// How many revisions share each blob.
+pub async fn dedup_chains(
+    State(state): State<AppState>,
+    Query(query): Query<AnalyticsQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /analytics/dedup from {addr}");
+
+    Ok(Json(state.db.dedup_chains(query.limit()).await?))
+}
@@ -1,10 +1,23 @@
-use crate::{AppState, errors::RouteError, utils::ConnectionAddr};
+use crate::{
+    AppState,
+    config::{UnmirroredAssets, UpstreamFallback},
+    errors::RouteError,
+    routes::{
+        ranged::{accepts_zstd, serve_ranged},
+        upstream::{proxy_upstream, redirect_upstream},
+    },
+    storage::{Storage, StorageBackend, StorageReader, asset_key, zstd_key},
+    utils::ConnectionAddr,
+};
+use async_compression::tokio::bufread::ZstdDecoder;
 use axum::{
     extract::{Path, Request, State},
-    response::IntoResponse,
+    http::{HeaderValue, header},
+    response::{IntoResponse, Response},
 };
 use reqwest::StatusCode;
 use std::path::{Component, Path as StdPath};
+use tokio::io::{AsyncReadExt, BufReader};
 use tower::ServiceExt;
 use tower_http::services::ServeFile;
 use tracing::{debug, warn};
@@ -32,6 +45,43 @@ impl IntoResponse for RouteError {
                 format!("Invalid file path: {file_path}"),
             )
                 .into_response(),
+            RouteError::NotMirrored(file) => (
+                StatusCode::NOT_FOUND,
+                format!("File is not mirrored by this server: {file}"),
+            )
+                .into_response(),
+            RouteError::Upstream(err, url) => {
+                warn!(error = %err, url = %url, "Failed to fetch asset from upstream");
+                (
+                    StatusCode::BAD_GATEWAY,
+                    format!("Failed to fetch asset from upstream: {url}"),
+                )
+                    .into_response()
+            }
+            RouteError::AssetRead(err) => {
+                warn!(error = %err, "Failed to read stored asset");
+                (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            }
+            RouteError::Storage(err) => {
+                warn!(error = %err, "Storage error occurred");
+                (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            }
+            RouteError::UnsupportedFormat(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            RouteError::ObjectProperty(err) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to decode object: {err}"),
+            )
+                .into_response(),
+            RouteError::Texture(err) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to convert texture: {err}"),
+            )
+                .into_response(),
+            RouteError::Search(err) => {
+                (StatusCode::BAD_REQUEST, format!("Invalid search: {err}")).into_response()
+            }
         }
     }
 }
@@ -54,24 +104,175 @@ pub async fn file(
         return Err(RouteError::BadRequest(file_path));
     }
 
-    // If the file is LatestFileList.xml or LatestFileList.bin, we know that it belongs to the current revision
-    let revision_for_asset = if file_path.contains("LatestFileList") {
-        revision
+    // If the file is LatestFileList.xml or LatestFileList.bin, we know that it belongs to the current revision.
+    // Manifests are never stored compressed and always downloaded first, so there's no location to look up.
+    let (revision_for_asset, location) = if file_path.contains("LatestFileList") {
+        (revision, None)
     } else {
-        state
+        let Some(location) = state
             .db
-            .get_revision_for_asset(revision, file_path.clone())
+            .get_revision_for_asset(revision.clone(), file_path.clone())
             .await?
-            .ok_or_else(|| RouteError::NotFound(file_path.clone()))?
+        else {
+            return serve_tar(&state, revision, file_path, req).await;
+        };
+
+        if !location.mirrored {
+            return match (state.config.server.unmirrored, &location.url_prefix) {
+                (Some(UnmirroredAssets::Redirect), Some(url_prefix)) => {
+                    Ok(redirect_upstream(url_prefix, &file_path))
+                }
+                _ => Err(RouteError::NotMirrored(file_path)),
+            };
+        }
+
+        (location.origin_revision.clone(), Some(location))
     };
 
-    let path = std::env::current_dir()?
-        .join(&state.config.fetcher.save_directory)
-        .join(revision_for_asset)
-        .join(file_path);
+    let key = asset_key(&revision_for_asset, &file_path);
+    let content_type = mime_guess::from_path(&file_path).first_or_octet_stream();
 
-    match ServeFile::new(path).oneshot(req).await {
+    // Plain blobs are served as they are
+    if let Some(stored_size) = state.storage.head(&key).await? {
+        return serve_plain(&state.storage, key, stored_size, content_type.as_ref(), req).await;
+    }
+
+    let Some(location) = location else {
+        return Err(RouteError::NotFound(file_path));
+    };
+    let size = u64::from(location.size);
+
+    // Assets may be stored zstd-compressed at rest, in which case only `<file>.zst` exists
+    let compressed_key = zstd_key(&key);
+    let Some(compressed_size) = state.storage.head(&compressed_key).await? else {
+        // Known, but not downloaded yet
+        return match (state.config.server.fallback, &location.url_prefix) {
+            (Some(UpstreamFallback::Redirect), Some(url_prefix)) => {
+                Ok(redirect_upstream(url_prefix, &file_path))
+            }
+            (Some(UpstreamFallback::Proxy), Some(url_prefix)) => {
+                proxy_upstream(&state, url_prefix, &file_path, key, &location, req.method()).await
+            }
+            _ => Err(RouteError::NotFound(file_path)),
+        };
+    };
+
+    // Clients that understand zstd get the stored bytes as-is
+    if accepts_zstd(req.headers()) {
+        if let Some(path) = state.storage.local_path(&key) {
+            let serve = ServeFile::new(std::env::current_dir()?.join(path)).precompressed_zstd();
+            return serve_file(serve, req).await;
+        }
+
+        let storage = state.storage.clone();
+        let mut response =
+            serve_ranged(
+                req.method(),
+                req.headers(),
+                compressed_size,
+                content_type.as_ref(),
+                |start| async move {
+                    open_range(&storage, &compressed_key, start, compressed_size).await
+                },
+            )
+            .await
+            .map_err(RouteError::AssetRead)?;
+
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("zstd"));
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        return Ok(response);
+    }
+
+    let storage = state.storage.clone();
+    serve_ranged(
+        req.method(),
+        req.headers(),
+        size,
+        content_type.as_ref(),
+        |start| async move {
+            let reader = storage
+                .get_range(&compressed_key, None)
+                .await
+                .map_err(std::io::Error::other)?;
+            let mut decoder = ZstdDecoder::new(BufReader::new(reader));
+
+            // zstd streams aren't seekable, so skip ahead by decompressing into the void
+            tokio::io::copy(&mut (&mut decoder).take(start), &mut tokio::io::sink()).await?;
+            Ok(decoder)
+        },
+    )
+    .await
+    .map_err(RouteError::AssetRead)
+}
+
+/// Serves a tar that upstream packs assets of `revision` into. Tars are kept as they were downloaded, never compressed.
+async fn serve_tar(
+    state: &AppState,
+    revision: String,
+    tar_name: String,
+    req: Request,
+) -> Result<Response, RouteError> {
+    let location = state
+        .db
+        .get_revision_for_tar(revision, tar_name.clone())
+        .await?
+        .ok_or_else(|| RouteError::NotFound(tar_name.clone()))?;
+
+    let key = asset_key(&location.origin_revision, &tar_name);
+    let Some(stored_size) = state.storage.head(&key).await? else {
+        // Its size isn't in the manifest, so it can't be proxied, only redirected to
+        return match (state.config.server.fallback, &location.url_prefix) {
+            (Some(UpstreamFallback::Redirect), Some(url_prefix)) => {
+                Ok(redirect_upstream(url_prefix, &tar_name))
+            }
+            _ => Err(RouteError::NotFound(tar_name)),
+        };
+    };
+
+    let content_type = mime_guess::from_path(&tar_name).first_or_octet_stream();
+    serve_plain(&state.storage, key, stored_size, content_type.as_ref(), req).await
+}
+
+/// Serves an uncompressed blob, straight from disk if the storage is local.
+async fn serve_plain(
+    storage: &Storage,
+    key: String,
+    stored_size: u64,
+    content_type: &str,
+    req: Request,
+) -> Result<Response, RouteError> {
+    if let Some(path) = storage.local_path(&key) {
+        return serve_file(ServeFile::new(std::env::current_dir()?.join(path)), req).await;
+    }
+
+    let storage = storage.clone();
+    serve_ranged(
+        req.method(),
+        req.headers(),
+        stored_size,
+        content_type,
+        |start| async move { open_range(&storage, &key, start, stored_size).await },
+    )
+    .await
+    .map_err(RouteError::AssetRead)
+}
+
+async fn serve_file(serve: ServeFile, req: Request) -> Result<Response, RouteError> {
+    match serve.oneshot(req).await {
         Ok(res) => Ok(res.into_response()),
         Err(err) => match err {},
     }
 }
+
+async fn open_range(
+    storage: &Storage,
+    key: &str,
+    start: u64,
+    end: u64,
+) -> std::io::Result<StorageReader> {
+    storage
+        .get_range(key, Some(start..end))
+        .await
+        .map_err(std::io::Error::other)
+}
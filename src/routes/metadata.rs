@@ -0,0 +1,24 @@
+use crate::{AppState, errors::RouteError, utils::ConnectionAddr};
+use axum::{
+    Json,
+    extract::{Path, State},
+    response::IntoResponse,
+};
+use tracing::debug;
+
+/// Every asset row of a revision, which is what replication followers need to mirror it.
+pub async fn get_revision_metadata(
+    State(state): State<AppState>,
+    Path(revision): Path<String>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /metadata/{revision} from {addr}");
+
+    let metadata = state
+        .db
+        .get_revision_metadata(revision.clone())
+        .await?
+        .ok_or(RouteError::NotFound(revision))?;
+
+    Ok(Json(metadata))
+}
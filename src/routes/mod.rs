@@ -1,3 +1,12 @@
+pub mod analytics;
 pub mod file;
 pub mod latest;
+pub mod metadata;
+pub mod preview;
+pub mod ranged;
 pub mod revisions;
+pub mod search;
+pub mod status;
+pub mod strings;
+pub mod upstream;
+pub mod wad;
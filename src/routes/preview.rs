@@ -0,0 +1,210 @@
+use crate::{
+    AppState,
+    dds::decode_dds,
+    errors::RouteError,
+    routes::wad::if_none_match,
+    storage::{LocalStorage, asset_key},
+    utils::ConnectionAddr,
+    wad::{is_stored, open_entry},
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use image::{ImageFormat, imageops::FilterType};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::{io::Cursor, path::PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, warn};
+
+/// Textures larger than this aren't converted, they'd have to be held in memory at once
+const MAX_TEXTURE_SIZE: u32 = 64 * 1024 * 1024;
+const MAX_PREVIEW_SIZE: u32 = 4096;
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewQuery {
+    /// Longest edge of a thumbnail, the full image if omitted
+    size: Option<u32>,
+}
+
+/// A DDS texture inside a WAD as PNG.
+pub async fn wad_entry_preview(
+    State(state): State<AppState>,
+    Path((revision, wad_name, inner_path)): Path<(String, String, String)>,
+    Query(query): Query<PreviewQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+    headers: HeaderMap,
+) -> Result<Response, RouteError> {
+    debug!("GET /preview/{revision}/wad/{wad_name}/{inner_path} from {addr}");
+
+    let location = state
+        .db
+        .get_wad_entry(revision, wad_name, inner_path.clone())
+        .await?
+        .ok_or_else(|| RouteError::NotFound(inner_path.clone()))?;
+    let entry = location.entry;
+    let key = asset_key(&location.origin_revision, &location.file_name);
+    if !is_stored(&state.storage, &key).await? {
+        return Err(RouteError::NotFound(inner_path));
+    }
+
+    let source = Source {
+        crc: entry.crc,
+        size: entry.size,
+    };
+    preview(&state, source, query.size, &headers, async {
+        let mut data = Vec::with_capacity(entry.size as usize);
+        open_entry(&state.storage, &key, &entry)
+            .await?
+            .read_to_end(&mut data)
+            .await
+            .map_err(RouteError::AssetRead)?;
+        Ok(data)
+    })
+    .await
+}
+
+/// A loose DDS texture of a revision as PNG.
+pub async fn asset_preview(
+    State(state): State<AppState>,
+    Path((revision, file_path)): Path<(String, String)>,
+    Query(query): Query<PreviewQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+    headers: HeaderMap,
+) -> Result<Response, RouteError> {
+    debug!("GET /preview/{revision}/{file_path} from {addr}");
+
+    let location = state
+        .db
+        .get_revision_for_asset(revision, file_path.clone())
+        .await?
+        .ok_or_else(|| RouteError::NotFound(file_path.clone()))?;
+    if !location.mirrored {
+        return Err(RouteError::NotMirrored(file_path));
+    }
+    let key = asset_key(&location.origin_revision, &file_path);
+
+    let source = Source {
+        crc: location.crc,
+        size: location.size,
+    };
+    preview(&state, source, query.size, &headers, async {
+        let mut reader = state
+            .storage
+            .open_blob(&key)
+            .await?
+            .ok_or(RouteError::NotFound(file_path))?;
+        let mut data = Vec::with_capacity(location.size as usize);
+        reader
+            .read_to_end(&mut data)
+            .await
+            .map_err(RouteError::AssetRead)?;
+        Ok(data)
+    })
+    .await
+}
+
+/// Identifies a texture by content, so previews are shared by every revision and WAD containing it.
+struct Source {
+    crc: u32,
+    size: u32,
+}
+
+/// Answers with the cached preview of `source`, converting it with the data `read` returns on a cache miss.
+async fn preview(
+    state: &AppState,
+    source: Source,
+    size: Option<u32>,
+    headers: &HeaderMap,
+    read: impl Future<Output = Result<Vec<u8>, RouteError>>,
+) -> Result<Response, RouteError> {
+    let size = size.map(|size| size.clamp(1, MAX_PREVIEW_SIZE));
+    let name = match size {
+        Some(size) => format!("{:08x}-{:x}-{size}", source.crc, source.size),
+        None => format!("{:08x}-{:x}-full", source.crc, source.size),
+    };
+
+    let etag = HeaderValue::from_str(&format!("\"{name}\"")).expect("ETag is valid ASCII");
+    if if_none_match(headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    let cache = state
+        .config
+        .server
+        .preview_cache
+        .as_deref()
+        .map_or_else(|| PathBuf::from("cache"), PathBuf::from)
+        .join("previews");
+    let path = cache.join(format!("{name}.png"));
+
+    let png = match tokio::fs::read(&path).await {
+        Ok(png) => png,
+        Err(_) => {
+            if source.size > MAX_TEXTURE_SIZE {
+                return Err(RouteError::UnsupportedFormat(
+                    "Texture is too large to be converted".to_string(),
+                ));
+            }
+
+            let data = read.await?;
+            // Decoding and encoding are CPU-bound
+            let png = tokio::task::spawn_blocking(move || convert(&data, size))
+                .await
+                .map_err(|e| RouteError::AssetRead(e.into()))??;
+
+            if let Err(e) = store(&cache, &name, &png).await {
+                warn!(path = %path.display(), error = %e, "Failed to cache preview");
+            }
+            png
+        }
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("image/png")),
+            (header::ETAG, etag),
+        ],
+        png,
+    )
+        .into_response())
+}
+
+/// Decodes a DDS texture and encodes it as PNG, scaled down to fit into `size` x `size` if given.
+fn convert(data: &[u8], size: Option<u32>) -> Result<Vec<u8>, RouteError> {
+    let mut image = decode_dds(data)?;
+    if let Some(size) = size
+        && (image.width() > size || image.height() > size)
+    {
+        let scale = f64::from(size) / f64::from(image.width().max(image.height()));
+        let width = ((f64::from(image.width()) * scale).round() as u32).max(1);
+        let height = ((f64::from(image.height()) * scale).round() as u32).max(1);
+        image = image::imageops::resize(&image, width, height, FilterType::Triangle);
+    }
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| RouteError::AssetRead(std::io::Error::other(e)))?;
+
+    Ok(png)
+}
+
+/// Writes a preview into the cache, going through a temporary file so readers never see a partial one.
+async fn store(cache: &std::path::Path, name: &str, png: &[u8]) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(cache).await?;
+
+    // Unique per request, so concurrent conversions of the same texture don't write into each other's file
+    let path = cache.join(format!("{name}.png"));
+    let (file, part_path) = LocalStorage::part_file(&path)?.into_parts();
+    let mut file = tokio::fs::File::from_std(file);
+    file.write_all(png).await?;
+    file.flush().await?;
+
+    tokio::fs::rename(&part_path, &path).await?;
+    // It's gone already, don't try to delete it again
+    let _ = part_path.keep();
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, Method, header},
+    response::{IntoResponse, Response},
+};
+use reqwest::StatusCode;
+use std::{io, ops::RangeInclusive};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::ReaderStream;
+
+/// Returns whether the client listed `zstd` in its `Accept-Encoding` header (and didn't disable it with `q=0`).
+pub fn accepts_zstd(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let disabled = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+
+            name.eq_ignore_ascii_case("zstd") && !disabled
+        })
+}
+
+/// Serves a body of `len` bytes that can't be handed to `ServeFile` directly, while keeping its Range/HEAD semantics.
+///
+/// `open` is called with the first byte offset the client asked for and must return a reader positioned there.
+/// Only single ranges are supported, just like `ServeFile`, everything else is answered with `416`.
+pub async fn serve_ranged<F, Fut, R>(
+    method: &Method,
+    headers: &HeaderMap,
+    len: u64,
+    content_type: &str,
+    open: F,
+) -> io::Result<Response>
+where
+    F: FnOnce(u64) -> Fut,
+    Fut: Future<Output = io::Result<R>>,
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let range = match headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => match parse_single_range(value, len) {
+            Some(range) => Some(range),
+            None => {
+                return Ok((
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+                )
+                    .into_response());
+            }
+        },
+        None => None,
+    };
+
+    let (status, start, body_len) = match &range {
+        Some(range) => (
+            StatusCode::PARTIAL_CONTENT,
+            *range.start(),
+            range.end() - range.start() + 1,
+        ),
+        None => (StatusCode::OK, 0, len),
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"))
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, body_len);
+
+    if let Some(range) = &range {
+        builder = builder.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{len}", range.start(), range.end()),
+        );
+    }
+
+    let body = if method == Method::HEAD || body_len == 0 {
+        Body::empty()
+    } else {
+        let reader = open(start).await?;
+        Body::from_stream(ReaderStream::new(reader.take(body_len)))
+    };
+
+    builder
+        .body(body)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn parse_single_range(value: &str, len: u64) -> Option<RangeInclusive<u64>> {
+    let mut ranges = http_range_header::parse_range_header(value)
+        .ok()?
+        .validate(len)
+        .ok()?;
+
+    if ranges.len() == 1 {
+        ranges.pop()
+    } else {
+        None
+    }
+}
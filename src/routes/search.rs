@@ -0,0 +1,60 @@
+use crate::{
+    AppState,
+    errors::RouteError,
+    search::{Pattern, SearchFilter, SearchMode},
+    utils::ConnectionAddr,
+};
+use axum::{
+    Json,
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use tracing::debug;
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    #[serde(default)]
+    mode: SearchMode,
+    revision: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    file_type: Option<u32>,
+    limit: Option<usize>,
+}
+
+/// Assets and WAD entries whose name matches a substring, glob or regular expression.
+pub async fn search(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /search?q={} from {addr}", query.q);
+
+    let pattern = Pattern::new(&query.q, query.mode)?;
+    let filter = SearchFilter {
+        revision: query.revision,
+        from: query.from,
+        to: query.to,
+        file_type: query.file_type,
+        limit: query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+    };
+    let names = [&filter.revision, &filter.from, &filter.to]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let results = state
+        .db
+        .search(pattern, filter)
+        .await?
+        .ok_or(RouteError::NotFound(names))?;
+
+    Ok(Json(results))
+}
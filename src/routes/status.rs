@@ -0,0 +1,14 @@
+use crate::{AppState, utils::ConnectionAddr};
+use axum::{Json, extract::State, response::IntoResponse};
+use serde_json::json;
+use tracing::debug;
+
+/// Reports whether the revision checker currently refuses to fetch, and why.
+pub async fn get_status(
+    State(state): State<AppState>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> impl IntoResponse {
+    debug!("GET /status from {}", addr);
+
+    Json(json!({ "refusal": state.status.refusal() }))
+}
@@ -0,0 +1,89 @@
+use crate::{
+    AppState,
+    errors::RouteError,
+    routes::search::{DEFAULT_LIMIT, MAX_LIMIT},
+    search::{Pattern, SearchMode},
+    utils::ConnectionAddr,
+};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use tracing::debug;
+
+#[derive(Debug, Deserialize)]
+pub struct StringQuery {
+    /// Only strings of this locale, e.g. `English`
+    locale: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StringSearchQuery {
+    q: String,
+    #[serde(default)]
+    mode: SearchMode,
+    /// Only strings of this locale, e.g. `English`
+    locale: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Strings of a revision whose key or text matches a substring, glob or regular expression.
+pub async fn search_strings(
+    State(state): State<AppState>,
+    Path(revision): Path<String>,
+    Query(query): Query<StringSearchQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /strings/{revision}?q={} from {addr}", query.q);
+
+    let pattern = Pattern::new(&query.q, query.mode)?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let results = state
+        .db
+        .search_strings(revision.clone(), pattern, query.locale, limit)
+        .await?
+        .ok_or(RouteError::NotFound(revision))?;
+
+    Ok(Json(results))
+}
+
+/// Every string stored under a key in a revision, across locales and tables.
+pub async fn lookup_string(
+    State(state): State<AppState>,
+    Path((revision, key)): Path<(String, String)>,
+    Query(query): Query<StringQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /strings/{revision}/{key} from {addr}");
+
+    let strings = state
+        .db
+        .lookup_string(revision.clone(), key.clone(), query.locale)
+        .await?
+        .ok_or(RouteError::NotFound(revision))?;
+    if strings.is_empty() {
+        return Err(RouteError::NotFound(key));
+    }
+
+    Ok(Json(strings))
+}
+
+/// Strings that were added, removed or changed between two revisions.
+pub async fn string_diff(
+    State(state): State<AppState>,
+    Path((from, to)): Path<(String, String)>,
+    Query(query): Query<StringQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /diff/{from}/{to}/strings from {addr}");
+
+    let diff = state
+        .db
+        .diff_strings(from.clone(), to.clone(), query.locale)
+        .await?
+        .ok_or_else(|| RouteError::NotFound(format!("{from} or {to}")))?;
+
+    Ok(Json(diff))
+}
@@ -0,0 +1,153 @@
+use crate::{
+    AppState,
+    db::AssetLocation,
+    errors::RouteError,
+    fetcher::throttle::BandwidthLimiter,
+    storage::{DownloadClaim, StorageBackend, zstd_key},
+    utils::verified,
+};
+use async_compression::{Level, tokio::bufread::ZstdEncoder};
+use axum::{
+    body::Body,
+    http::{Method, header},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, TryStreamExt, stream};
+use reqwest::{Client, StatusCode};
+use std::{io, sync::LazyLock, time::Duration};
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+use tracing::{debug, warn};
+
+static UPSTREAM_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .user_agent("KingsIsle Patcher")
+        .tcp_keepalive(Duration::from_mins(1))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to create upstream HTTP client")
+});
+
+/// `302 Found` pointing at the asset on the patch server.
+pub fn redirect_upstream(url_prefix: &str, file_path: &str) -> Response {
+    (
+        StatusCode::FOUND,
+        [(header::LOCATION, format!("{url_prefix}/{file_path}"))],
+    )
+        .into_response()
+}
+
+/// Streams the asset from the patch server to the client, while caching it in storage under `key`.
+///
+/// The full file is always sent (Range requests are answered with `200`), since that's what gets cached.
+/// If the client goes away midway, the download still finishes so the cache is complete.
+/// Nothing is cached unless the download matches the size and CRC of `location`, or while the fetcher is downloading the asset.
+/// The download counts against `bandwidth_limit` like the fetcher's.
+pub async fn proxy_upstream(
+    state: &AppState,
+    url_prefix: &str,
+    file_path: &str,
+    key: String,
+    location: &AssetLocation,
+    method: &Method,
+) -> Result<Response, RouteError> {
+    let (size, crc) = (u64::from(location.size), location.crc);
+    let content_type = mime_guess::from_path(file_path).first_or_octet_stream();
+
+    if method == Method::HEAD {
+        return Ok((
+            [
+                (header::CONTENT_TYPE, content_type.to_string()),
+                (header::CONTENT_LENGTH, size.to_string()),
+            ],
+            Body::empty(),
+        )
+            .into_response());
+    }
+
+    let url = format!("{url_prefix}/{file_path}");
+    let response = UPSTREAM_CLIENT
+        .get(&url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| RouteError::Upstream(e, url.clone()))?;
+
+    let content_length = response.content_length().unwrap_or(size);
+    let headers = [
+        (header::CONTENT_TYPE, content_type.to_string()),
+        (header::CONTENT_LENGTH, content_length.to_string()),
+    ];
+
+    let body = throttled(response.bytes_stream(), state.downloads.limiter.clone());
+
+    // The fetcher or another request is already downloading this asset, just pass it through
+    let Some(claim) = DownloadClaim::try_claim(&key) else {
+        return Ok((headers, Body::from_stream(body)).into_response());
+    };
+
+    let storage = state.storage.clone();
+    let compression = state.config.fetcher.compression.clone();
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(16);
+
+    tokio::spawn(async move {
+        let body = Box::pin(body.map_err(io::Error::other));
+        let body = verified(body, size, crc).then(move |chunk| {
+            let tx = tx.clone();
+
+            async move {
+                match chunk {
+                    Ok(chunk) => {
+                        // The client may have gone away, keep caching anyway
+                        let _ = tx.send(Ok(chunk.clone())).await;
+                        Ok(chunk)
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(io::Error::new(e.kind(), e.to_string()))).await;
+                        Err(e)
+                    }
+                }
+            }
+        });
+        let reader = StreamReader::new(Box::pin(body));
+
+        let result = match compression {
+            Some(config) => {
+                let encoder = ZstdEncoder::with_quality(reader, Level::Precise(config.level));
+                storage.put_stream(&zstd_key(&key), encoder).await
+            }
+            None => storage.put_stream(&key, reader).await,
+        };
+
+        match result {
+            Ok(_) => debug!(key = %key, "cached asset from upstream"),
+            Err(e) => warn!(error = %e, key = %key, "failed to cache asset from upstream"),
+        }
+
+        drop(claim);
+    });
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+
+    Ok((headers, Body::from_stream(body)).into_response())
+}
+
+/// Holds every chunk of `body` back until `limiter` lets it through.
+fn throttled<S>(body: S, limiter: Option<BandwidthLimiter>) -> impl Stream<Item = S::Item>
+where
+    S: Stream<Item = reqwest::Result<Bytes>>,
+{
+    body.then(move |chunk| {
+        let limiter = limiter.clone();
+
+        async move {
+            if let (Ok(chunk), Some(limiter)) = (&chunk, &limiter) {
+                limiter.consume(chunk.len()).await;
+            }
+            chunk
+        }
+    })
+}
@@ -0,0 +1,171 @@
+use crate::{
+    AppState,
+    errors::RouteError,
+    object_property::{DeserializerOptions, deserialize},
+    routes::ranged::serve_ranged,
+    storage::asset_key,
+    utils::ConnectionAddr,
+    wad::{WadEntry, is_stored, open_entry},
+};
+use axum::{
+    Json,
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, header},
+    response::IntoResponse,
+};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+use tracing::debug;
+
+/// Entries larger than this aren't decoded into JSON, they'd have to be held in memory at once
+const MAX_DECODED_SIZE: u32 = 64 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct EntryQuery {
+    /// `json` decodes ObjectProperty data (`BINd` files) with the configured type list
+    format: Option<String>,
+}
+
+/// Serves a single file from inside a WAD, decompressed.
+///
+/// `wad_name` is the WAD's path within the revision (URL-encoded) or just its file name, e.g. `Root.wad`.
+pub async fn wad_entry(
+    State(state): State<AppState>,
+    Path((revision, wad_name, inner_path)): Path<(String, String, String)>,
+    Query(query): Query<EntryQuery>,
+    ConnectionAddr(addr): ConnectionAddr,
+    req: Request,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /{revision}/wad/{wad_name}/{inner_path} from {addr}");
+
+    let json = match query.format.as_deref() {
+        None | Some("raw") => false,
+        Some("json") => true,
+        Some(format) => {
+            return Err(RouteError::UnsupportedFormat(format!(
+                "Unsupported format: {format}"
+            )));
+        }
+    };
+
+    let location = state
+        .db
+        .get_wad_entry(revision, wad_name, inner_path.clone())
+        .await?
+        .ok_or_else(|| RouteError::NotFound(inner_path.clone()))?;
+
+    let etag = entry_etag(&location.entry, json);
+    if if_none_match(req.headers(), &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+
+    let key = asset_key(&location.origin_revision, &location.file_name);
+    if !is_stored(&state.storage, &key).await? {
+        return Err(RouteError::NotFound(inner_path));
+    }
+    if json {
+        let value = decode_entry(&state, &key, &location.entry).await?;
+        return Ok(([(header::ETAG, etag)], Json(value)).into_response());
+    }
+
+    let content_type = mime_guess::from_path(&inner_path).first_or_octet_stream();
+    let storage = state.storage.clone();
+    let entry = location.entry;
+
+    let mut response = serve_ranged(
+        req.method(),
+        req.headers(),
+        u64::from(entry.size),
+        content_type.as_ref(),
+        |start| async move {
+            let mut reader = open_entry(&storage, &key, &entry)
+                .await
+                .map_err(std::io::Error::other)?;
+
+            // Decompressed data isn't seekable either, so skip ahead into the void
+            tokio::io::copy(&mut (&mut reader).take(start), &mut tokio::io::sink()).await?;
+            Ok(reader)
+        },
+    )
+    .await
+    .map_err(RouteError::AssetRead)?;
+
+    response.headers_mut().insert(header::ETAG, etag);
+    Ok(response)
+}
+
+/// Reads the whole entry and decodes it as ObjectProperty data.
+async fn decode_entry(
+    state: &AppState,
+    key: &str,
+    entry: &WadEntry,
+) -> Result<serde_json::Value, RouteError> {
+    let (Some(types), Some(config)) = (&state.types, &state.config.object_property) else {
+        return Err(RouteError::UnsupportedFormat(
+            "Decoding game data isn't configured on this server".to_string(),
+        ));
+    };
+    if entry.size > MAX_DECODED_SIZE {
+        return Err(RouteError::UnsupportedFormat(format!(
+            "{} is too large to be decoded",
+            entry.name
+        )));
+    }
+
+    let mut data = Vec::with_capacity(entry.size as usize);
+    open_entry(&state.storage, key, entry)
+        .await?
+        .read_to_end(&mut data)
+        .await
+        .map_err(RouteError::AssetRead)?;
+
+    let options = DeserializerOptions {
+        shallow: config.shallow.unwrap_or(false),
+        property_mask: config
+            .property_mask
+            .unwrap_or(DeserializerOptions::default().property_mask),
+        ..DeserializerOptions::default()
+    };
+    let types = types.clone();
+
+    // Decoding is CPU-bound and entries can be large
+    tokio::task::spawn_blocking(move || deserialize(&data, &types, &options))
+        .await
+        .map_err(|e| RouteError::AssetRead(e.into()))?
+        .map_err(RouteError::ObjectProperty)
+}
+
+/// Entries are identified by their CRC and size, which don't change unless the content does.
+fn entry_etag(entry: &WadEntry, json: bool) -> HeaderValue {
+    let suffix = if json { "-json" } else { "" };
+    HeaderValue::from_str(&format!("\"{:08x}-{:x}{suffix}\"", entry.crc, entry.size))
+        .expect("ETag is valid ASCII")
+}
+
+pub fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag.as_bytes() == etag.as_bytes())
+}
+
+/// Added, removed and modified entries of a WAD between two revisions.
+pub async fn wad_diff(
+    State(state): State<AppState>,
+    Path((from, to, wad_name)): Path<(String, String, String)>,
+    ConnectionAddr(addr): ConnectionAddr,
+) -> Result<impl IntoResponse, RouteError> {
+    debug!("GET /diff/{from}/{to}/wad/{wad_name} from {addr}");
+
+    let diff = state
+        .db
+        .diff_wad(from, to, wad_name.clone())
+        .await?
+        .ok_or(RouteError::NotFound(wad_name))?;
+
+    Ok(Json(diff))
+}
@@ -0,0 +1,162 @@
+use crate::{errors::SearchError, utils::compile_glob};
+use globset::GlobMatcher;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Shortest substring the trigram index can look up, shorter queries scan every name
+const MIN_LITERAL_LENGTH: usize = 3;
+/// Compiled regular expressions may not grow larger than this
+const REGEX_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// How the query of a search is interpreted.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Case-insensitive substring
+    #[default]
+    Text,
+    /// Glob matching the whole name, with the same syntax as `[fetcher.filter]`
+    Glob,
+    /// Regular expression matching anywhere in the name unless anchored
+    Regex,
+}
+
+/// A compiled search query.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Text(String),
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn new(query: &str, mode: SearchMode) -> Result<Self, SearchError> {
+        if query.is_empty() {
+            return Err(SearchError::EmptyQuery);
+        }
+
+        let pattern = match mode {
+            SearchMode::Text => Self::Text(query.to_lowercase()),
+            SearchMode::Glob => Self::Glob(
+                compile_glob(query)
+                    .map_err(|e| SearchError::InvalidGlob(e, query.to_string()))?
+                    .compile_matcher(),
+            ),
+            SearchMode::Regex => Self::Regex(
+                RegexBuilder::new(query)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map_err(SearchError::InvalidRegex)?,
+            ),
+        };
+
+        Ok(pattern)
+    }
+
+    /// A substring every matching name contains, if there is one long enough to look up in the trigram index.
+    ///
+    /// Only a hint, names containing it still have to be checked with `is_match`.
+    pub fn literal(&self) -> Option<String> {
+        let literal = match self {
+            Self::Text(text) => text.clone(),
+            Self::Glob(matcher) => glob_literal(matcher.glob().glob()),
+            // Extracting required literals from a regex isn't worth it, they're matched against every name
+            Self::Regex(_) => return None,
+        };
+
+        Some(literal).filter(|literal| literal.chars().count() >= MIN_LITERAL_LENGTH)
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            Self::Text(text) => name.to_lowercase().contains(text.as_str()),
+            Self::Glob(matcher) => matcher.is_match(name),
+            Self::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// The longest run of plain characters in a glob, outside of character classes and alternatives.
+fn glob_literal(glob: &str) -> String {
+    let mut longest = String::new();
+    let mut run = String::new();
+    let mut class = false;
+    let mut alternatives = 0;
+
+    for c in glob.chars() {
+        let literal = match c {
+            '[' if !class => {
+                class = true;
+                false
+            }
+            ']' if class => {
+                class = false;
+                false
+            }
+            '{' if !class => {
+                alternatives += 1;
+                false
+            }
+            '}' if !class && alternatives > 0 => {
+                alternatives -= 1;
+                false
+            }
+            '*' | '?' | '\\' => false,
+            _ => !class && alternatives == 0,
+        };
+
+        if literal {
+            run.push(c);
+        } else if run.len() > longest.len() {
+            longest = std::mem::take(&mut run);
+        } else {
+            run.clear();
+        }
+    }
+
+    if run.len() > longest.len() {
+        run
+    } else {
+        longest
+    }
+}
+
+/// Which revisions a search looks at, and how many hits it returns.
+#[derive(Debug, Clone)]
+pub struct SearchFilter {
+    /// Only this revision
+    pub revision: Option<String>,
+    /// Revisions from this one on
+    pub from: Option<String>,
+    /// Revisions up to and including this one
+    pub to: Option<String>,
+    /// Only assets (or entries of WADs) of this `FileType`
+    pub file_type: Option<u32>,
+    pub limit: usize,
+}
+
+/// An asset or WAD entry whose name matches a search.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    /// Path of the asset, or of the WAD the entry is in
+    pub file_name: String,
+    /// Path of the entry inside the WAD, `None` for assets
+    pub entry_name: Option<String>,
+    /// `FileType` of the asset (or WAD) in the newest matching revision
+    pub file_type: u32,
+    /// Revisions within the filter that contain it, oldest first
+    pub revisions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResults {
+    /// Ordered by name
+    pub hits: Vec<SearchHit>,
+    /// Whether there were more hits than the limit
+    pub truncated: bool,
+}
+
+/// Turns `literal` into an FTS5 phrase, which the trigram tokenizer matches as a case-insensitive substring.
+pub fn fts_phrase(literal: &str) -> String {
+    format!("\"{}\"", literal.replace('"', "\"\""))
+}
@@ -0,0 +1,223 @@
+use crate::{
+    errors::StorageError,
+    storage::{StorageBackend, StorageReader, StoredObject},
+};
+use std::{
+    io::{ErrorKind, SeekFrom},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+use tokio::{
+    fs::{File, create_dir_all},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter},
+};
+
+/// Stores objects as plain files below a root directory (`fetcher.save_directory`).
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P>(root: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Resolves `key` below the root, refusing anything that would escape it.
+    pub fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let relative = Path::new(key);
+        let escapes = relative.components().any(|c| {
+            matches!(
+                c,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_)
+            )
+        });
+
+        if key.is_empty() || escapes {
+            return Err(StorageError::InvalidKey(key.to_string()));
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    /// Free space on the volume of the root directory. The root may not exist yet, so the closest existing ancestor is checked.
+    pub fn available_space(&self) -> Result<u64, StorageError> {
+        let mut dir = self.root.as_path();
+        while !dir.exists() {
+            match dir.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => dir = parent,
+                _ => {
+                    dir = Path::new(".");
+                    break;
+                }
+            }
+        }
+
+        fs4::available_space(dir).map_err(StorageError::Io)
+    }
+
+    /// Creates a uniquely named `.part` file next to `path`, so concurrent writes of the same key don't share one.
+    /// It's deleted when dropped, unless it was renamed away.
+    pub(crate) fn part_file(path: &Path) -> std::io::Result<NamedTempFile> {
+        let parent = path.parent().unwrap_or(Path::new("."));
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        let prefix = format!("{name}.");
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(&prefix).suffix(".part");
+        // Temporary files are only accessible by their owner, stored objects should get the usual permissions
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+
+        builder.tempfile_in(parent)
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    async fn put_stream<R>(&self, key: &str, mut reader: R) -> Result<u64, StorageError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let final_path = self.path(key)?;
+
+        // Check if parent dir exists, else create it
+        if let Some(parent) = final_path.parent() {
+            create_dir_all(parent)
+                .await
+                .map_err(StorageError::CreateDir)?;
+        }
+
+        let (file, part_path) = Self::part_file(&final_path)
+            .map_err(StorageError::Io)?
+            .into_parts();
+        let mut writer = BufWriter::with_capacity(128 * 1024, File::from_std(file)); // TODO: Let the user configure this buffer size(?)
+
+        // If there is an error during the write, the partial file is removed when `part_path` is dropped
+        let written = async {
+            let written = tokio::io::copy(&mut reader, &mut writer).await?;
+            writer.flush().await?;
+            Ok(written)
+        }
+        .await
+        .map_err(StorageError::Io)?;
+
+        // Rename the .part file to the final filename
+        tokio::fs::rename(&part_path, &final_path)
+            .await
+            .map_err(StorageError::Rename)?;
+        // It's gone already, don't try to delete it again
+        let _ = part_path.keep();
+
+        Ok(written)
+    }
+
+    async fn get_range(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<StorageReader, StorageError> {
+        let mut file = File::open(self.path(key)?)
+            .await
+            .map_err(StorageError::Io)?;
+
+        match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start))
+                    .await
+                    .map_err(StorageError::Io)?;
+                Ok(Box::pin(file.take(range.end - range.start)))
+            }
+            None => Ok(Box::pin(file)),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<u64>, StorageError> {
+        match tokio::fs::metadata(self.path(key)?).await {
+            Ok(meta) if meta.is_file() => Ok(Some(meta.len())),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageError::Io(e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(StorageError::Io(e)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let to_path = self.path(to)?;
+        if let Some(parent) = to_path.parent() {
+            create_dir_all(parent)
+                .await
+                .map_err(StorageError::CreateDir)?;
+        }
+
+        tokio::fs::rename(self.path(from)?, to_path)
+            .await
+            .map_err(StorageError::Rename)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        let root = self.root.clone();
+        let prefix = prefix.to_string();
+
+        // Only walk the directory the prefix points into, not the whole archive
+        let start = match prefix.rfind('/') {
+            Some(idx) => self.path(&prefix[..idx])?,
+            None => root.clone(),
+        };
+
+        tokio::task::spawn_blocking(move || -> Result<Vec<StoredObject>, StorageError> {
+            let mut objects = Vec::new();
+            let mut pending = vec![start];
+
+            while let Some(dir) = pending.pop() {
+                let entries = match std::fs::read_dir(&dir) {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(StorageError::Io(e)),
+                };
+
+                for entry in entries {
+                    let entry = entry.map_err(StorageError::Io)?;
+                    let meta = entry.metadata().map_err(StorageError::Io)?;
+                    let path = entry.path();
+
+                    if meta.is_dir() {
+                        pending.push(path);
+                        continue;
+                    }
+
+                    let Ok(relative) = path.strip_prefix(&root) else {
+                        continue;
+                    };
+                    let key = relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+
+                    if key.starts_with(&prefix) {
+                        objects.push(StoredObject {
+                            key,
+                            size: meta.len(),
+                        });
+                    }
+                }
+            }
+
+            Ok(objects)
+        })
+        .await
+        .map_err(|e| StorageError::Io(e.into()))?
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{
+    config::{AppConfig, CompressionConfig, StorageConfig},
+    errors::StorageError,
+};
+use async_compression::{
+    Level,
+    tokio::bufread::{ZstdDecoder, ZstdEncoder},
+};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, LazyLock, Mutex},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, BufReader},
+    sync::Notify,
+};
+
+mod local;
+mod s3;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+/// Asset keys that are currently being downloaded, by the fetcher or a proxied request, and how to wake up whoever
+/// waits for them.
+static IN_FLIGHT: LazyLock<Mutex<HashMap<String, Arc<Notify>>>> = LazyLock::new(Default::default);
+
+/// Exclusive right to download the blob of an asset key, released on drop.
+///
+/// Keeps the fetcher and proxied requests from downloading the same asset at the same time.
+#[derive(Debug)]
+pub struct DownloadClaim {
+    key: String,
+}
+
+impl DownloadClaim {
+    /// Claims `key`, or returns `None` if someone else is downloading it.
+    pub fn try_claim(key: &str) -> Option<Self> {
+        let mut in_flight = IN_FLIGHT.lock().expect("in-flight set poisoned");
+        if in_flight.contains_key(key) {
+            return None;
+        }
+
+        in_flight.insert(key.to_string(), Arc::new(Notify::new()));
+        Some(Self {
+            key: key.to_string(),
+        })
+    }
+
+    /// Waits until `key` is free and claims it.
+    pub async fn claim(key: &str) -> Self {
+        loop {
+            // Created while the set is locked, so a release right after can't be missed
+            let released = {
+                let mut in_flight = IN_FLIGHT.lock().expect("in-flight set poisoned");
+                match in_flight.get(key) {
+                    Some(notify) => notify.clone().notified_owned(),
+                    None => {
+                        in_flight.insert(key.to_string(), Arc::new(Notify::new()));
+                        return Self {
+                            key: key.to_string(),
+                        };
+                    }
+                }
+            };
+
+            // Everyone waiting wakes up, one of them gets the claim and the others wait again
+            released.await;
+        }
+    }
+}
+
+impl Drop for DownloadClaim {
+    fn drop(&mut self) {
+        let notify = IN_FLIGHT
+            .lock()
+            .expect("in-flight set poisoned")
+            .remove(&self.key);
+
+        if let Some(notify) = notify {
+            notify.notify_waiters();
+        }
+    }
+}
+
+/// Reader over (a range of) a stored object.
+pub type StorageReader = Pin<Box<dyn AsyncRead + Send>>;
+
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+}
+
+/// Everything Aurorium needs from the place it archives revisions in.
+///
+/// Keys are `/`-separated relative paths, e.g. `V_r773351.Wizard_1_570_0_Live/Data/GameData/Root.wad`.
+pub trait StorageBackend {
+    /// Streams `reader` into `key` and returns the amount of bytes written.
+    /// The object must only become visible under `key` once it was written completely.
+    fn put_stream<R>(
+        &self,
+        key: &str,
+        reader: R,
+    ) -> impl Future<Output = Result<u64, StorageError>> + Send
+    where
+        R: AsyncRead + Unpin + Send;
+
+    /// Opens `key` for reading, limited to `range` if one is given.
+    fn get_range(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> impl Future<Output = Result<StorageReader, StorageError>> + Send;
+
+    /// Returns the size of `key`, or `None` if it doesn't exist.
+    fn head(&self, key: &str) -> impl Future<Output = Result<Option<u64>, StorageError>> + Send;
+
+    fn exists(&self, key: &str) -> impl Future<Output = Result<bool, StorageError>> + Send
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.head(key).await?.is_some()) }
+    }
+
+    /// Deletes `key`. Deleting a key that doesn't exist is not an error.
+    fn delete(&self, key: &str) -> impl Future<Output = Result<(), StorageError>> + Send;
+
+    /// Moves `from` to `to`, replacing `to` if it exists.
+    fn rename(&self, from: &str, to: &str)
+    -> impl Future<Output = Result<(), StorageError>> + Send;
+
+    /// Lists all objects whose key starts with `prefix`.
+    fn list(
+        &self,
+        prefix: &str,
+    ) -> impl Future<Output = Result<Vec<StoredObject>, StorageError>> + Send;
+}
+
+/// The configured storage backend.
+///
+/// This is an enum rather than a trait object, so the async trait methods don't need to be boxed.
+#[derive(Clone)]
+pub enum Storage {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+impl Storage {
+    pub fn from_config(config: &AppConfig) -> miette::Result<Self> {
+        let storage = match &config.storage {
+            None | Some(StorageConfig::Local) => {
+                Self::Local(LocalStorage::new(&config.fetcher.save_directory))
+            }
+            Some(StorageConfig::S3(s3_config)) => Self::S3(S3Storage::new(s3_config)?),
+        };
+
+        Ok(storage)
+    }
+
+    /// Path of `key` on the local file system, if the backend is local.
+    /// This lets the file route hand files straight to `ServeFile`.
+    pub fn local_path(&self, key: &str) -> Option<PathBuf> {
+        match self {
+            Self::Local(local) => local.path(key).ok(),
+            Self::S3(_) => None,
+        }
+    }
+
+    /// Free space left for new objects, or `None` if the backend has no meaningful limit (object storage).
+    pub fn available_space(&self) -> Result<Option<u64>, StorageError> {
+        match self {
+            Self::Local(local) => local.available_space().map(Some),
+            Self::S3(_) => Ok(None),
+        }
+    }
+
+    /// Opens the blob stored under `key` for reading, decompressing it if only `zstd_key(key)` exists.
+    /// Returns `None` if neither exists.
+    pub async fn open_blob(&self, key: &str) -> Result<Option<StorageReader>, StorageError> {
+        if self.exists(key).await? {
+            return self.get_range(key, None).await.map(Some);
+        }
+
+        let compressed_key = zstd_key(key);
+        if self.exists(&compressed_key).await? {
+            let reader = self.get_range(&compressed_key, None).await?;
+            return Ok(Some(Box::pin(ZstdDecoder::new(BufReader::new(reader)))));
+        }
+
+        Ok(None)
+    }
+
+    /// Copies a local file into `key`, or zstd-compressed into `zstd_key(key)` if `compression` is set.
+    pub async fn put_file(
+        &self,
+        key: &str,
+        path: &Path,
+        compression: Option<&CompressionConfig>,
+    ) -> Result<u64, StorageError> {
+        let file = File::open(path).await.map_err(StorageError::Io)?;
+        let reader = BufReader::new(file);
+
+        match compression {
+            Some(config) => {
+                let encoder = ZstdEncoder::with_quality(reader, Level::Precise(config.level));
+                self.put_stream(&zstd_key(key), encoder).await
+            }
+            None => self.put_stream(key, reader).await,
+        }
+    }
+
+    /// Like `put_file`, but consumes the file. Uncompressed files are renamed into place on local storage if possible.
+    pub async fn move_file(
+        &self,
+        key: &str,
+        path: &Path,
+        compression: Option<&CompressionConfig>,
+    ) -> Result<u64, StorageError> {
+        if let (Self::Local(local), None) = (self, compression) {
+            let size = tokio::fs::metadata(path)
+                .await
+                .map_err(StorageError::Io)?
+                .len();
+            let target = local.path(key)?;
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(StorageError::CreateDir)?;
+            }
+
+            // Renaming fails across file systems, copying still works there
+            if tokio::fs::rename(path, &target).await.is_ok() {
+                return Ok(size);
+            }
+        }
+
+        let written = self.put_file(key, path, compression).await?;
+        tokio::fs::remove_file(path)
+            .await
+            .map_err(StorageError::Io)?;
+
+        Ok(written)
+    }
+
+    /// Reads the whole object into memory. Only meant for small objects like manifests.
+    pub async fn read_to_end(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        let mut reader = self.get_range(key, None).await?;
+        let mut buffer = Vec::new();
+        reader
+            .read_to_end(&mut buffer)
+            .await
+            .map_err(StorageError::Io)?;
+
+        Ok(buffer)
+    }
+}
+
+impl StorageBackend for Storage {
+    async fn put_stream<R>(&self, key: &str, reader: R) -> Result<u64, StorageError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        match self {
+            Self::Local(local) => local.put_stream(key, reader).await,
+            Self::S3(s3) => s3.put_stream(key, reader).await,
+        }
+    }
+
+    async fn get_range(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<StorageReader, StorageError> {
+        match self {
+            Self::Local(local) => local.get_range(key, range).await,
+            Self::S3(s3) => s3.get_range(key, range).await,
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<u64>, StorageError> {
+        match self {
+            Self::Local(local) => local.head(key).await,
+            Self::S3(s3) => s3.head(key).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match self {
+            Self::Local(local) => local.delete(key).await,
+            Self::S3(s3) => s3.delete(key).await,
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        match self {
+            Self::Local(local) => local.rename(from, to).await,
+            Self::S3(s3) => s3.rename(from, to).await,
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        match self {
+            Self::Local(local) => local.list(prefix).await,
+            Self::S3(s3) => s3.list(prefix).await,
+        }
+    }
+}
+
+/// Key of a file belonging to a revision.
+pub fn asset_key(revision: &str, file_name: &str) -> String {
+    format!("{revision}/{file_name}")
+}
+
+/// Key of the zstd-compressed blob that is stored instead of `key` when compression at rest is enabled.
+pub fn zstd_key(key: &str) -> String {
+    format!("{key}.zst")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn waiters_get_the_claim_once_it_is_released() {
+        let key = "V_r1/Data/Claimed.wad";
+        let claim = DownloadClaim::try_claim(key).unwrap();
+        assert!(DownloadClaim::try_claim(key).is_none());
+
+        let waiters: Vec<_> = (0..3)
+            .map(|_| tokio::spawn(async move { drop(DownloadClaim::claim(key).await) }))
+            .collect();
+        tokio::task::yield_now().await;
+        drop(claim);
+
+        // Each waiter is woken by the release before it, no polling involved
+        for waiter in waiters {
+            timeout(Duration::from_millis(100), waiter)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        assert!(DownloadClaim::try_claim(key).is_some());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    config::S3Config,
+    errors::StorageError,
+    storage::{StorageBackend, StorageReader, StoredObject},
+};
+use futures_util::{StreamExt, TryStreamExt};
+use object_store::{
+    GetOptions, GetRange, ObjectStore, aws::AmazonS3Builder, buffered::BufWriter, path::Path,
+};
+use std::{ops::Range, sync::Arc};
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+/// Stores objects in an S3-compatible bucket (AWS, MinIO, Garage, R2, ...).
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    store: Arc<dyn ObjectStore>,
+    prefix: Option<String>,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config) -> Result<Self, StorageError> {
+        // Anything not set in config.toml may still come from the usual AWS_* environment variables
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(&config.bucket);
+
+        if let Some(region) = &config.region {
+            builder = builder.with_region(region);
+        }
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.with_endpoint(endpoint);
+        }
+        if let Some(access_key_id) = &config.access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = &config.secret_access_key {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+        if let Some(allow_http) = config.allow_http {
+            builder = builder.with_allow_http(allow_http);
+        }
+        if let Some(virtual_hosted_style) = config.virtual_hosted_style {
+            builder = builder.with_virtual_hosted_style_request(virtual_hosted_style);
+        }
+
+        let store = builder.build().map_err(StorageError::Build)?;
+
+        Ok(Self::with_store(Arc::new(store), config.prefix.as_deref()))
+    }
+
+    /// Stores objects in `store`, under `prefix` if there is one.
+    pub fn with_store(store: Arc<dyn ObjectStore>, prefix: Option<&str>) -> Self {
+        Self {
+            store,
+            prefix: prefix
+                .map(|prefix| prefix.trim_matches('/').to_string())
+                .filter(|prefix| !prefix.is_empty()),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<Path, StorageError> {
+        let full_key = match &self.prefix {
+            Some(prefix) => format!("{prefix}/{key}"),
+            None => key.to_string(),
+        };
+
+        Path::parse(&full_key).map_err(|_| StorageError::InvalidKey(key.to_string()))
+    }
+
+    fn key(&self, path: &Path) -> String {
+        let path = path.as_ref();
+        match &self.prefix {
+            Some(prefix) => path
+                .strip_prefix(prefix.as_str())
+                .map_or(path, |key| key.trim_start_matches('/'))
+                .to_string(),
+            None => path.to_string(),
+        }
+    }
+}
+
+impl StorageBackend for S3Storage {
+    async fn put_stream<R>(&self, key: &str, mut reader: R) -> Result<u64, StorageError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        // Small objects are uploaded with a single PUT, larger ones as a multipart upload.
+        // Either way S3 only exposes the object once the upload completed.
+        let mut writer = BufWriter::new(self.store.clone(), self.path(key)?);
+
+        let result = async {
+            let written = tokio::io::copy(&mut reader, &mut writer).await?;
+            writer.shutdown().await?;
+            Ok(written)
+        }
+        .await;
+
+        match result {
+            Ok(written) => Ok(written),
+            Err(e) => {
+                let _ = writer.abort().await;
+                Err(StorageError::Io(e))
+            }
+        }
+    }
+
+    async fn get_range(
+        &self,
+        key: &str,
+        range: Option<Range<u64>>,
+    ) -> Result<StorageReader, StorageError> {
+        let options = GetOptions {
+            range: range.map(GetRange::Bounded),
+            ..Default::default()
+        };
+
+        let result = self
+            .store
+            .get_opts(&self.path(key)?, options)
+            .await
+            .map_err(StorageError::ObjectStore)?;
+        let stream = result.into_stream().map_err(std::io::Error::other);
+
+        Ok(Box::pin(StreamReader::new(stream)))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<u64>, StorageError> {
+        match self.store.head(&self.path(key)?).await {
+            Ok(meta) => Ok(Some(meta.size)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(StorageError::ObjectStore(e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match self.store.delete(&self.path(key)?).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(e) => Err(StorageError::ObjectStore(e)),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        // S3 has no rename, object_store copies and deletes
+        self.store
+            .rename(&self.path(from)?, &self.path(to)?)
+            .await
+            .map_err(StorageError::ObjectStore)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredObject>, StorageError> {
+        // `ObjectStore::list` only matches whole path segments, so list the parent and filter
+        let parent = match prefix.rfind('/') {
+            Some(idx) => Some(self.path(&prefix[..idx])?),
+            None => self.prefix.as_deref().map(Path::from),
+        };
+
+        let objects = self
+            .store
+            .list(parent.as_ref())
+            .map_err(StorageError::ObjectStore)
+            .map_ok(|meta| StoredObject {
+                key: self.key(&meta.location),
+                size: meta.size,
+            })
+            .try_filter(|object| std::future::ready(object.key.starts_with(prefix)))
+            .collect::<Vec<_>>()
+            .await;
+
+        objects.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use tokio::io::AsyncReadExt;
+
+    async fn read(storage: &S3Storage, key: &str, range: Option<Range<u64>>) -> Vec<u8> {
+        let mut data = Vec::new();
+        storage
+            .get_range(key, range)
+            .await
+            .unwrap()
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn round_trips_objects() {
+        let store = Arc::new(InMemory::new());
+        let storage = S3Storage::with_store(store.clone(), Some("/mirror/"));
+
+        let written = storage
+            .put_stream("V_r100/Data/Root.wad", &b"KIWAD root"[..])
+            .await
+            .unwrap();
+        assert_eq!(written, 10);
+        storage
+            .put_stream("V_r100/Data/Other.wad", &b"other"[..])
+            .await
+            .unwrap();
+        storage
+            .put_stream("V_r101/Data/Root.wad", &b"newer"[..])
+            .await
+            .unwrap();
+        // Written below the prefix
+        assert!(
+            store
+                .head(&Path::from("mirror/V_r100/Data/Root.wad"))
+                .await
+                .is_ok()
+        );
+
+        assert_eq!(
+            read(&storage, "V_r100/Data/Root.wad", None).await,
+            b"KIWAD root"
+        );
+        assert_eq!(
+            read(&storage, "V_r100/Data/Root.wad", Some(6..10)).await,
+            b"root"
+        );
+        assert_eq!(
+            storage.head("V_r100/Data/Root.wad").await.unwrap(),
+            Some(10)
+        );
+        assert_eq!(storage.head("V_r100/Data/Missing.wad").await.unwrap(), None);
+
+        // Prefixes don't have to end at a path segment
+        let mut listed: Vec<_> = storage
+            .list("V_r100/Data/R")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| (object.key, object.size))
+            .collect();
+        listed.sort();
+        assert_eq!(listed, [("V_r100/Data/Root.wad".to_string(), 10)]);
+        assert_eq!(storage.list("V_r10").await.unwrap().len(), 3);
+
+        storage
+            .rename("V_r100/Data/Root.wad", "V_r102/Data/Root.wad")
+            .await
+            .unwrap();
+        assert_eq!(storage.head("V_r100/Data/Root.wad").await.unwrap(), None);
+        assert_eq!(
+            read(&storage, "V_r102/Data/Root.wad", None).await,
+            b"KIWAD root"
+        );
+
+        storage.delete("V_r102/Data/Root.wad").await.unwrap();
+        // Deleting what isn't there isn't an error
+        storage.delete("V_r102/Data/Root.wad").await.unwrap();
+        assert_eq!(storage.head("V_r102/Data/Root.wad").await.unwrap(), None);
+        assert_eq!(storage.list("V_r102").await.unwrap().len(), 0);
+        assert!(
+            storage
+                .get_range("V_r102/Data/Root.wad", None)
+                .await
+                .is_err()
+        );
+    }
+}
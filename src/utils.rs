@@ -1,9 +1,17 @@
-use std::net::SocketAddr;
+use std::{
+    fs::File,
+    io::{self, ErrorKind, Read},
+    net::SocketAddr,
+    path::Path,
+};
 
 use axum::{
     extract::{ConnectInfo, FromRequestParts},
     http::request::Parts,
 };
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, stream};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use reqwest::StatusCode;
 
 pub enum Endianness {
@@ -31,6 +39,39 @@ pub fn hex_decode(hex_string: &str, endianness: &Endianness) -> Option<Vec<u8>>
     bytes
 }
 
+/// Compiles a single glob where `*` stays within one path segment and `**` spans several.
+pub fn compile_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+pub fn compile_glob_set<S>(patterns: &[S]) -> Result<GlobSet, globset::Error>
+where
+    S: AsRef<str>,
+{
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(compile_glob(pattern.as_ref())?);
+    }
+
+    builder.build()
+}
+
+/// CRC32 of a whole file, as the manifest lists it.
+pub fn file_crc(path: &Path) -> std::io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(hasher.finalize()),
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ConnectionAddr(pub String);
 
@@ -59,3 +100,37 @@ where
         Ok(ConnectionAddr(connection_info.0.ip().to_string()))
     }
 }
+
+/// Passes `body` through, but ends it with an error if it doesn't have the manifest's `size` and `crc`.
+/// The storage backend then discards what it wrote instead of exposing a damaged blob.
+pub fn verified<S>(body: S, size: u64, crc: u32) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>> + Unpin,
+{
+    let state = Some((body, crc32fast::Hasher::new(), 0u64));
+
+    stream::unfold(state, move |state| async move {
+        let (mut body, mut hasher, mut length) = state?;
+
+        match body.next().await {
+            Some(Ok(chunk)) => {
+                hasher.update(&chunk);
+                length += chunk.len() as u64;
+                Some((Ok(chunk), Some((body, hasher, length))))
+            }
+            Some(Err(e)) => Some((Err(e), None)),
+            None => {
+                let actual = hasher.finalize();
+                (length != size || actual != crc).then(|| {
+                    let e = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "got {length} bytes with CRC {actual}, the manifest lists {size} bytes with CRC {crc}"
+                        ),
+                    );
+                    (Err(e), None)
+                })
+            }
+        }
+    })
+}
@@ -0,0 +1,310 @@
+use crate::{
+    errors::{StorageError, WadError},
+    storage::{Storage, StorageBackend, StorageReader, zstd_key},
+};
+use async_compression::tokio::bufread::{ZlibDecoder, ZstdDecoder};
+use serde::Serialize;
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+
+const MAGIC: &[u8; 5] = b"KIWAD";
+/// Longest entry name we accept, anything longer means the header is garbage
+const MAX_NAME_LENGTH: u32 = 4096;
+
+/// The header of a KIWAD archive (`.wad`): everything before the entry data.
+#[derive(Debug, Clone, Serialize)]
+pub struct WadArchive {
+    pub version: u32,
+    /// Only present from version 2 on
+    pub flags: Option<u8>,
+    pub entries: Vec<WadEntry>,
+}
+
+/// A file inside a KIWAD archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct WadEntry {
+    /// `/`-separated path inside the archive, e.g. `WizardGUI/Login.gui`
+    pub name: String,
+    /// Offset of the entry's data from the start of the archive
+    pub offset: u32,
+    /// Uncompressed size
+    pub size: u32,
+    /// Size of the zlib stream, only meaningful if `compressed` is set
+    pub compressed_size: u32,
+    pub compressed: bool,
+    /// CRC of the stored data
+    pub crc: u32,
+}
+
+impl WadEntry {
+    /// Amount of bytes the entry takes up in the archive.
+    pub fn stored_size(&self) -> u32 {
+        if self.compressed {
+            self.compressed_size
+        } else {
+            self.size
+        }
+    }
+}
+
+/// Entry-level changes of a WAD between two revisions, compared by CRC and size.
+#[derive(Debug, Clone, Serialize)]
+pub struct WadDiff {
+    /// Path of the WAD within the newer revision
+    pub wad: String,
+    pub added: Vec<WadEntry>,
+    pub removed: Vec<WadEntry>,
+    pub modified: Vec<ModifiedEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModifiedEntry {
+    pub name: String,
+    pub from: WadEntry,
+    pub to: WadEntry,
+}
+
+impl WadDiff {
+    /// Entries are matched by name. Offsets aren't compared, they shift whenever an earlier entry changes.
+    pub fn new(wad: String, from: &WadArchive, to: &WadArchive) -> Self {
+        let old: HashMap<&str, &WadEntry> = from
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry))
+            .collect();
+        let new: HashMap<&str, &WadEntry> = to
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry))
+            .collect();
+
+        let mut diff = Self {
+            wad,
+            added: Vec::new(),
+            removed: Vec::new(),
+            modified: Vec::new(),
+        };
+        for entry in &to.entries {
+            match old.get(entry.name.as_str()) {
+                None => diff.added.push(entry.clone()),
+                Some(previous) if previous.crc != entry.crc || previous.size != entry.size => {
+                    diff.modified.push(ModifiedEntry {
+                        name: entry.name.clone(),
+                        from: (*previous).clone(),
+                        to: entry.clone(),
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = from
+            .entries
+            .iter()
+            .filter(|entry| !new.contains_key(entry.name.as_str()))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+/// Parses the header and file table of a KIWAD archive, reading nothing past them.
+///
+/// All integers are little endian:
+/// `"KIWAD" version:u32 count:u32 [flags:u8 if version >= 2]`, followed by `count` entries of
+/// `offset:u32 size:u32 compressed_size:u32 compressed:u8 crc:u32 name_length:u32 name` (the name is NUL-terminated).
+pub async fn read_wad_header<R>(reader: &mut R) -> Result<WadArchive, WadError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut magic = [0u8; 5];
+    reader.read_exact(&mut magic).await?;
+    if &magic != MAGIC {
+        return Err(WadError::InvalidMagic);
+    }
+
+    let version = reader.read_u32_le().await?;
+    let count = reader.read_u32_le().await?;
+    let flags = if version >= 2 {
+        Some(reader.read_u8().await?)
+    } else {
+        None
+    };
+
+    // The count comes straight from the file, so it doesn't get to decide the allocation
+    let mut entries = Vec::with_capacity(count.min(1 << 16) as usize);
+    for _ in 0..count {
+        let offset = reader.read_u32_le().await?;
+        let size = reader.read_u32_le().await?;
+        let compressed_size = reader.read_u32_le().await?;
+        let compressed = reader.read_u8().await? != 0;
+        let crc = reader.read_u32_le().await?;
+
+        let name_length = reader.read_u32_le().await?;
+        if name_length > MAX_NAME_LENGTH {
+            return Err(WadError::NameTooLong(name_length));
+        }
+        let mut name = vec![0u8; name_length as usize];
+        reader.read_exact(&mut name).await?;
+        if name.last() == Some(&0) {
+            name.pop();
+        }
+
+        entries.push(WadEntry {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            offset,
+            size,
+            compressed_size,
+            compressed,
+            crc,
+        });
+    }
+
+    Ok(WadArchive {
+        version,
+        flags,
+        entries,
+    })
+}
+
+/// Whether the WAD under `key` is in storage, plain or compressed. WADs indexed from a prefetched header may not be
+/// downloaded yet.
+pub async fn is_stored(storage: &Storage, key: &str) -> Result<bool, StorageError> {
+    Ok(storage.exists(key).await? || storage.exists(&zstd_key(key)).await?)
+}
+
+/// Opens the data of `entry` inside the WAD stored under `key`, zlib-decompressed if the entry is compressed.
+///
+/// Plain WADs are read with a range request. WADs that are stored zstd-compressed have to be decompressed up to the entry.
+pub async fn open_entry(
+    storage: &Storage,
+    key: &str,
+    entry: &WadEntry,
+) -> Result<StorageReader, StorageError> {
+    let start = u64::from(entry.offset);
+    let end = start + u64::from(entry.stored_size());
+
+    let stored: StorageReader = if storage.exists(key).await? {
+        storage.get_range(key, Some(start..end)).await?
+    } else {
+        let reader = storage.get_range(&zstd_key(key), None).await?;
+        let mut decoder = ZstdDecoder::new(BufReader::new(reader));
+        tokio::io::copy(&mut (&mut decoder).take(start), &mut tokio::io::sink())
+            .await
+            .map_err(StorageError::Io)?;
+        Box::pin(decoder.take(end - start))
+    };
+
+    if entry.compressed {
+        Ok(Box::pin(ZlibDecoder::new(BufReader::new(stored))))
+    } else {
+        Ok(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file table entry as it appears in the header, the name gets its NUL terminator here.
+    fn entry(offset: u32, size: u32, compressed: Option<u32>, crc: u32, name: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(offset.to_le_bytes());
+        bytes.extend(size.to_le_bytes());
+        bytes.extend(compressed.unwrap_or(0).to_le_bytes());
+        bytes.push(u8::from(compressed.is_some()));
+        bytes.extend(crc.to_le_bytes());
+        bytes.extend((name.len() as u32 + 1).to_le_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    fn header(version: u32, flags: Option<u8>, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(version.to_le_bytes());
+        bytes.extend((entries.len() as u32).to_le_bytes());
+        bytes.extend(flags);
+        for entry in entries {
+            bytes.extend(entry);
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn reads_version_1_header() {
+        let data = header(
+            1,
+            None,
+            &[
+                entry(100, 12, None, 0xDEAD_BEEF, "Readme.txt"),
+                entry(112, 300, Some(40), 7, "WizardGUI/Login.gui"),
+            ],
+        );
+
+        let archive = read_wad_header(&mut &data[..]).await.unwrap();
+        assert_eq!(archive.version, 1);
+        assert_eq!(archive.flags, None);
+        assert_eq!(archive.entries.len(), 2);
+
+        let readme = &archive.entries[0];
+        assert_eq!(readme.name, "Readme.txt");
+        assert_eq!(
+            (readme.offset, readme.size, readme.crc),
+            (100, 12, 0xDEAD_BEEF)
+        );
+        assert!(!readme.compressed);
+        assert_eq!(readme.stored_size(), 12);
+
+        let gui = &archive.entries[1];
+        assert_eq!(gui.name, "WizardGUI/Login.gui");
+        assert!(gui.compressed);
+        assert_eq!((gui.size, gui.stored_size()), (300, 40));
+    }
+
+    #[tokio::test]
+    async fn reads_version_2_flags() {
+        let data = header(2, Some(1), &[entry(0, 5, None, 1, "a.xml")]);
+
+        let archive = read_wad_header(&mut &data[..]).await.unwrap();
+        assert_eq!(archive.version, 2);
+        assert_eq!(archive.flags, Some(1));
+        assert_eq!(archive.entries[0].name, "a.xml");
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_file_table() {
+        let mut data = header(2, Some(0), &[]);
+        data.extend(b"entry data that isn't part of the header");
+
+        let archive = read_wad_header(&mut &data[..]).await.unwrap();
+        assert!(archive.entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_garbage() {
+        let result = read_wad_header(&mut &b"PK\x03\x04 not a wad"[..]).await;
+        assert!(matches!(result, Err(WadError::InvalidMagic)));
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_file_table() {
+        let data = header(1, None, &[entry(0, 5, None, 1, "a.xml")]);
+
+        for length in [3, 10, data.len() - 1] {
+            let result = read_wad_header(&mut &data[..length]).await;
+            assert!(matches!(result, Err(WadError::Read(_))), "{length} bytes");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_huge_names() {
+        let mut data = header(1, None, &[entry(0, 5, None, 1, "a.xml")]);
+        // Name length of the only entry
+        let name_length = data.len() - "a.xml\0".len() - 4;
+        data[name_length..name_length + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let result = read_wad_header(&mut &data[..]).await;
+        assert!(matches!(result, Err(WadError::NameTooLong(u32::MAX))));
+    }
+}
@@ -162,7 +162,8 @@ impl WizardPatcher {
         Err(WizardPatcherError::RevisionParseError(url.to_string()))?
     }
 
-    fn extract_revision_number(name: &str) -> miette::Result<i64> {
+    /// Parses the number out of a revision name like `V_r773351.Wizard_1_570_0_Live`.
+    pub fn extract_revision_number(name: &str) -> miette::Result<i64> {
         if let Some(captures) = REVISION_RE.captures(name).and_then(|c| c.get(1)) {
             let revision_number = captures
                 .as_str()
@@ -1,6 +1,10 @@
 use crate::{errors::XmlParseError, revision::Asset};
 use quick_xml::{Reader, events::Event};
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
 
 enum Field {
     Src,
@@ -18,7 +22,13 @@ where
     P: AsRef<Path>,
 {
     let file = File::open(path).map_err(XmlParseError::FileOpen)?;
-    let reader = BufReader::with_capacity(1 << 20, file);
+    parse_file_list_from_reader(BufReader::with_capacity(1 << 20, file))
+}
+
+pub fn parse_file_list_from_reader<R>(reader: R) -> miette::Result<Vec<Asset>>
+where
+    R: BufRead,
+{
     let mut xml = Reader::from_reader(reader);
     xml.config_mut().trim_text(true);
 